@@ -1,5 +1,8 @@
 MAX_ISN_SIZE = 15
+# the relation types of the graphs made with the default graph config (see graph::RelationType),
+# the graphs with more relations record `relation_count` and `relation_version` in their metadata
 RELATION_COUNT = 7
+RELATION_VERSION = 4
 
 
 def numpy_zstd_monkeypatch():
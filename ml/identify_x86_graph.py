@@ -1,6 +1,7 @@
 from torch_geometric.data import Data
 from numpy.compat import os_fspath
 import numpy as np
+import json
 import torch
 
 # monkey patch numpy to support zstd for npz
@@ -27,6 +28,51 @@ def load_graph(filename, name=None):
     G.x_code = torch.from_numpy(npz['instruction_codes']).to(torch.long)
     G.x_size = torch.from_numpy(npz['instruction_sizes']).to(torch.long)
     G.y = torch.from_numpy(npz['instruction_labels']).to(torch.long)
+    # absent in the older graphs
+    if 'node_addresses' in npz:
+        G.x_address = torch.from_numpy(npz['node_addresses'].astype(np.int64))
+    # instruction start, instruction body, data, padding, unknown (see superset::Label)
+    if 'instruction_label_classes' in npz:
+        G.y_class = torch.from_numpy(npz['instruction_label_classes']).to(torch.long)
+    if 'instruction_function_starts' in npz:
+        G.y_function = torch.from_numpy(npz['instruction_function_starts']).to(torch.long)
+    # only present if the graph was made with --data-references or --resolve-pic
+    # the referenced addresses of each node, padded with -1 (a single column in the older graphs)
+    if 'instruction_references' in npz:
+        references = npz['instruction_references']
+        G.x_reference = torch.from_numpy(references.reshape(len(references), -1)).to(torch.long)
+    # only present if the graph was made with --jump-tables
+    if 'instruction_data_hints' in npz:
+        G.x_data_hint = torch.from_numpy(npz['instruction_data_hints']).to(torch.long)
+    # only present if the graph was made with --extra-features
+    for key in ['operand_kinds', 'prefixes', 'flags', 'memory_reads', 'memory_writes', 'stack_pointer_deltas']:
+        if f'instruction_{key}' in npz:
+            # torch does not support the unsigned types wider than u8
+            setattr(G, f'x_{key}', torch.from_numpy(npz[f'instruction_{key}'].astype(np.int64)))
+    # only present if selected by --node-features (see graph::NodeFeatures)
+    # the register sets are u64 bitmasks, so they are converted like the extra features
+    for key in ['falls_through', 'has_jump_target', 'uses', 'defines', 'is_invalid', 'is_privileged']:
+        if f'instruction_{key}' in npz:
+            setattr(G, f'x_{key}', torch.from_numpy(npz[f'instruction_{key}'].astype(np.int64)))
+    for key in ['protections', 'bytes']:
+        if f'node_{key}' in npz:
+            setattr(G, f'x_{key}', torch.from_numpy(npz[f'node_{key}']).to(torch.long))
+
+    # only present in the graphs of a window of a bigger sample (see graph::GraphWindow)
+    # the halo nodes are there for the context, the loss and the predictions should only use the core ones
+    if 'window_node_indices' in npz:
+        G.window_node_index = torch.from_numpy(npz['window_node_indices']).to(torch.long)
+        G.window_is_core = torch.from_numpy(npz['window_node_is_core']).to(torch.bool)
+
+    # how the superset was made (see superset::SupersetConfig), absent in the older graphs
+    if 'metadata' in npz:
+        G.metadata = json.loads(npz['metadata'].tobytes())
+        # the older graphs don't record the relations, they have at least the relations 0 to 6 (version 1)
+        G.relation_count = G.metadata.get('relation_count', 7)
+        G.relation_version = G.metadata.get('relation_version', 1)
+        # the sha256 of the sample and of the vocab file the codes index into (see graph::GraphMetadata)
+        G.sample_hash = G.metadata.get('sample_hash')
+        G.vocab_hash = G.metadata.get('vocab_hash')
 
     edge_idx = torch.from_numpy(npz['relations']).to(torch.long)
     edge_ty = torch.from_numpy(npz['relation_types'])
@@ -34,6 +80,9 @@ def load_graph(filename, name=None):
     G.num_edges = edge_idx.shape[0]
     G.edge_index = torch.swapaxes(edge_idx, 0, 1)
     G.edge_type = edge_ty
+    # how many times the edge was found (e.g. two registers depending on the same instruction), absent in the older graphs
+    if 'relation_multiplicities' in npz:
+        G.edge_multiplicity = torch.from_numpy(npz['relation_multiplicities']).to(torch.long)
 
     return G
 
@@ -1,4 +1,6 @@
 
+from typing import Optional
+
 import torch
 from torch import Tensor
 from torch_geometric.nn import Sequential, RGCNConv, Linear
@@ -11,7 +13,7 @@ import torchmetrics.classification
 VOCAB_SIZE = 502
 
 class IdentifyModel(torch.nn.Module):
-    def __init__(self) -> None:
+    def __init__(self, relation_count: Optional[int] = None) -> None:
         super().__init__()
 
         size_embed_size = 4
@@ -22,18 +24,21 @@ class IdentifyModel(torch.nn.Module):
             return torch.cat([x1, x2], dim=1)
 
         from identify_x86_data import RELATION_COUNT, MAX_ISN_SIZE
+        # the graphs made with the extra relations record their count in the metadata (see load_graph)
+        if relation_count is None:
+            relation_count = RELATION_COUNT
 
         self.model = Sequential('x_code, x_size, edge_index, edge_type', [
             (Embedding(num_embeddings=MAX_ISN_SIZE, embedding_dim=size_embed_size), 'x_size -> x_size'),
             (Embedding(num_embeddings=VOCAB_SIZE, embedding_dim=code_embed_size), 'x_code -> x_code'),
             (cat, 'x_size, x_code -> x'),
-            (RGCNConv(size_embed_size + code_embed_size, 24, RELATION_COUNT).jittable(), 'x, edge_index, edge_type -> x'),
+            (RGCNConv(size_embed_size + code_embed_size, 24, relation_count).jittable(), 'x, edge_index, edge_type -> x'),
             ReLU(inplace=True),
-            (RGCNConv(24, 16, RELATION_COUNT).jittable(), 'x, edge_index, edge_type -> x'),
+            (RGCNConv(24, 16, relation_count).jittable(), 'x, edge_index, edge_type -> x'),
             ReLU(inplace=True),
-            (RGCNConv(16, 8, RELATION_COUNT).jittable(), 'x, edge_index, edge_type -> x'),
+            (RGCNConv(16, 8, relation_count).jittable(), 'x, edge_index, edge_type -> x'),
             ReLU(inplace=True),
-            (RGCNConv(8, 4, RELATION_COUNT).jittable(), 'x, edge_index, edge_type -> x'),
+            (RGCNConv(8, 4, relation_count).jittable(), 'x, edge_index, edge_type -> x'),
             ReLU(inplace=True),
             Linear(4, 2),
         ])
@@ -44,11 +49,11 @@ class IdentifyModel(torch.nn.Module):
 
 
 class LightningModel(pl.LightningModule):
-    def __init__(self, true_instr_weight: float = 8.0, learning_rate: float = 3e-4):
+    def __init__(self, true_instr_weight: float = 8.0, learning_rate: float = 3e-4, relation_count: Optional[int] = None):
         super(LightningModel, self).__init__()
         self.save_hyperparameters()
 
-        self.model = IdentifyModel()
+        self.model = IdentifyModel(relation_count)
 
         self.loss = torch.nn.CrossEntropyLoss(
             weight = torch.tensor([
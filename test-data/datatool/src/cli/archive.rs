@@ -0,0 +1,95 @@
+use crate::cli::util::SampleStore;
+use crate::model::archive::{SampleArchive, SampleArchiveWriter};
+use indicatif::ProgressIterator;
+use std::path::{Component, Path, PathBuf};
+use tracing::info;
+
+#[derive(Debug, clap::Args)]
+pub struct PackSamples {
+    /// Directory with `.sample` files (or another sample archive)
+    samples_path: PathBuf,
+    archive_path: PathBuf,
+    /// Add the samples to an existing archive, skipping the ones already in it
+    #[clap(short, long)]
+    append: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct UnpackSamples {
+    archive_path: PathBuf,
+    output_path: PathBuf,
+}
+
+pub async fn action_pack_samples(args: PackSamples) -> anyhow::Result<()> {
+    let store = SampleStore::open(&args.samples_path)?;
+    let samples = store.names();
+    info!("Found {} samples", samples.len());
+
+    let mut writer = if args.append {
+        SampleArchiveWriter::append(&args.archive_path)?
+    } else {
+        SampleArchiveWriter::create(&args.archive_path)?
+    };
+
+    let mut skipped = 0;
+    for sample_name in samples.iter().progress() {
+        if args.append && writer.contains(sample_name) {
+            skipped += 1;
+            continue;
+        }
+        // the samples are copied as-is, without re-encoding
+        writer.add_raw(sample_name, &store.read_raw(sample_name)?)?;
+    }
+    writer.finish()?;
+
+    info!(
+        "Packed {} samples ({} already present)",
+        samples.len() - skipped,
+        skipped
+    );
+
+    Ok(())
+}
+
+/// Where a sample of the archive is unpacked to, the names can't point outside of the output directory
+fn unpacked_sample_path(output_path: &Path, sample_name: &str) -> anyhow::Result<PathBuf> {
+    let name = Path::new(sample_name);
+    anyhow::ensure!(
+        name.components().next().is_some()
+            && name.components().all(|c| matches!(c, Component::Normal(_))),
+        "Refusing to unpack the sample {:?} outside of the output directory",
+        sample_name
+    );
+    Ok(output_path.join(format!("{}.sample", sample_name)))
+}
+
+pub async fn action_unpack_samples(args: UnpackSamples) -> anyhow::Result<()> {
+    let archive = SampleArchive::open(&args.archive_path)?;
+    let samples = archive.names().collect::<Vec<_>>();
+    info!("Found {} samples", samples.len());
+
+    for sample_name in samples.iter().progress() {
+        let output_path = unpacked_sample_path(&args.output_path, sample_name)?;
+        std::fs::create_dir_all(output_path.parent().unwrap())?;
+        std::fs::write(&output_path, archive.read_raw(sample_name)?)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unpacked_sample_path() {
+        let output = Path::new("/tmp/samples");
+        assert_eq!(
+            unpacked_sample_path(output, "debian/buster/ls").unwrap(),
+            Path::new("/tmp/samples/debian/buster/ls.sample")
+        );
+        for name in ["../../x", "debian/../../x", "/etc/passwd", "./x", ""] {
+            assert!(unpacked_sample_path(output, name).is_err(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::cli::util::SampleStore;
+use crate::model::{AddressIndex, GraphConfig, SupersetConfig, SupersetSample};
+use indicatif::ProgressIterator;
+use prettytable::{row, Table};
+use rustc_hash::FxHashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Times the address lookups done by the graph construction with the hash map and the dense index
+#[derive(Debug, clap::Args)]
+pub struct BenchGraph {
+    /// Directory with `.sample` files or a sample archive
+    samples_path: PathBuf,
+    /// Only benchmark this many of the largest samples
+    #[clap(long, default_value_t = 5)]
+    largest: usize,
+    /// Take the best of this many runs
+    #[clap(long, default_value_t = 3)]
+    repeat: usize,
+    /// Also time the whole graph construction
+    #[clap(long)]
+    full: bool,
+    #[clap(flatten)]
+    superset: SupersetConfig,
+}
+
+/// Looks up the same addresses as [`crate::model::GraphSample::new`]: the next instructions, the overlapping ones and the jump targets
+fn lookup_workload(superset: &SupersetSample, get: impl Fn(u64) -> Option<u32>) -> u64 {
+    let mut checksum = 0u64;
+    for (&addr, instr) in superset.addresses.iter().zip(superset.instructions.iter()) {
+        let addr = addr as u64;
+        let next_addr = addr + instr.size as u64;
+        let found = (addr..=next_addr)
+            .chain(instr.jump_target())
+            .filter_map(&get);
+        for index in found {
+            checksum = checksum.wrapping_add(index as u64);
+        }
+    }
+    checksum
+}
+
+fn best_of<T>(repeat: usize, mut f: impl FnMut() -> T) -> (Duration, T) {
+    let mut best = None;
+    for _ in 0..repeat.max(1) {
+        let start = Instant::now();
+        let result = f();
+        let time = start.elapsed();
+        if best
+            .as_ref()
+            .map(|(best_time, _)| time < *best_time)
+            .unwrap_or(true)
+        {
+            best = Some((time, result));
+        }
+    }
+    best.unwrap()
+}
+
+fn format_time(time: Duration) -> String {
+    format!("{:.03}s", time.as_secs_f64())
+}
+
+pub(super) async fn action_bench_graph(args: BenchGraph) -> anyhow::Result<()> {
+    let store = SampleStore::open(&args.samples_path)?;
+    let samples = store.names();
+    info!("Found {} samples", samples.len());
+
+    // the superset has a node for each byte, so the image size is a good enough estimate of the graph size
+    let mut sizes = samples
+        .iter()
+        .progress()
+        .map(|sample_name| -> anyhow::Result<_> {
+            let sample = store.load(sample_name)?;
+            let size = sample.memory.iter().map(|i| i.data.len()).sum::<usize>();
+            Ok((size, sample_name))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    sizes.sort_by(|a, b| b.cmp(a));
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_BORDERS_ONLY);
+    table.set_titles(row![
+        "Sample",
+        "Nodes",
+        "Hash Map Build",
+        "Hash Map Lookups",
+        "Dense Build",
+        "Dense Lookups",
+        "Speedup",
+        "Graph",
+    ]);
+
+    for (_, sample_name) in sizes.into_iter().take(args.largest) {
+        let superset = store
+            .load(sample_name)?
+            .into_superset_with_config(&args.superset);
+
+        let (hash_build, hash_index) = best_of(args.repeat, || {
+            let mut index = FxHashMap::default();
+            for (i, &addr) in superset.addresses.iter().enumerate() {
+                index.insert(addr as u64, i as u32);
+            }
+            index
+        });
+        let (hash_lookups, hash_checksum) = best_of(args.repeat, || {
+            lookup_workload(&superset, |addr| hash_index.get(&addr).copied())
+        });
+        drop(hash_index);
+
+        let (dense_build, dense_index) =
+            best_of(args.repeat, || AddressIndex::new(&superset.addresses));
+        let (dense_lookups, dense_checksum) = best_of(args.repeat, || {
+            lookup_workload(&superset, |addr| dense_index.get(addr))
+        });
+        drop(dense_index);
+
+        anyhow::ensure!(
+            hash_checksum == dense_checksum,
+            "The indices disagree on {}",
+            sample_name
+        );
+
+        let speedup =
+            (hash_build + hash_lookups).as_secs_f64() / (dense_build + dense_lookups).as_secs_f64();
+        let nodes = superset.addresses.len();
+        let graph = if args.full {
+            let start = Instant::now();
+            drop(superset.into_graph(&GraphConfig::default()));
+            format_time(start.elapsed())
+        } else {
+            "-".to_string()
+        };
+
+        table.add_row(row![
+            sample_name,
+            nodes,
+            format_time(hash_build),
+            format_time(hash_lookups),
+            format_time(dense_build),
+            format_time(dense_lookups),
+            format!("{:.02}x", speedup),
+            graph,
+        ]);
+    }
+
+    println!("{}", table);
+
+    Ok(())
+}
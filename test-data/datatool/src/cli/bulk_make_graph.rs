@@ -1,5 +1,7 @@
-use crate::cli::util::collect_sample_paths;
-use crate::model::{CodeVocabBuilder, ExecutableSample};
+use crate::cli::util::{load_graph_config, window_output_path, SampleStore};
+use crate::model::{
+    CodeVocabBuilder, DataDependencyStats, GraphConfig, GraphSample, SupersetConfig,
+};
 use anyhow::Context;
 use indicatif::ParallelProgressIterator;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
@@ -10,11 +12,19 @@ use tracing::info;
 
 #[derive(Debug, clap::Args)]
 pub struct BulkMakeGraph {
+    /// Directory with `.sample` files or a sample archive
     samples_path: PathBuf,
     #[clap(short, long, default_value_t = 500)]
     vocab_size: usize,
     vocab_out_path: PathBuf,
     graphs_out_path: PathBuf,
+    #[clap(flatten)]
+    superset: SupersetConfig,
+    #[clap(flatten)]
+    graph: GraphConfig,
+    /// YAML file with the graph config, overrides the graph flags
+    #[clap(long)]
+    graph_config: Option<PathBuf>,
 }
 
 pub(super) async fn action_bulk_make_graph(args: BulkMakeGraph) -> anyhow::Result<()> {
@@ -23,7 +33,9 @@ pub(super) async fn action_bulk_make_graph(args: BulkMakeGraph) -> anyhow::Resul
         .build_global()
         .context("Initializing thread pool")?;
 
-    let samples = collect_sample_paths(&args.samples_path)?;
+    let graph_config = load_graph_config(args.graph.clone(), args.graph_config.as_deref())?;
+    let store = SampleStore::open(&args.samples_path)?;
+    let samples = store.names();
 
     info!("Found {} samples", samples.len());
 
@@ -32,12 +44,11 @@ pub(super) async fn action_bulk_make_graph(args: BulkMakeGraph) -> anyhow::Resul
     let vocab = samples
         .par_iter()
         .progress_count(samples.len() as u64)
-        .map(|sample_path| -> anyhow::Result<_> {
-            // info!("Processing {}", sample_path.display());
+        .map(|sample_name| -> anyhow::Result<_> {
+            // info!("Processing {}", sample_name);
             let mut b = CodeVocabBuilder::new();
-            let sample =
-                ExecutableSample::deserialize_from(&mut std::fs::File::open(&sample_path)?)?;
-            let superset = sample.into_superset();
+            let sample = store.load(sample_name)?;
+            let superset = sample.into_superset_with_config(&args.superset);
             b.add_sample(&superset);
             Ok(b)
         })
@@ -55,48 +66,60 @@ pub(super) async fn action_bulk_make_graph(args: BulkMakeGraph) -> anyhow::Resul
     samples
         .par_iter()
         // .progress_count(samples.len() as u64)
-        .try_for_each(|sample_path| -> anyhow::Result<()> {
+        .try_for_each(|sample_name| -> anyhow::Result<()> {
             let start = Instant::now();
-            let sample =
-                ExecutableSample::deserialize_from(&mut std::fs::File::open(&sample_path)?)?;
-            let superset_sample = sample.into_superset();
+            let sample = store.load(sample_name)?;
+            let sample_hash = sample.hash();
+            let superset_sample = sample.into_superset_with_config(&args.superset);
             info!(
                 "{:>150}: {:07} nodes",
-                sample_path.display(),
-                superset_sample.superset.len(),
+                sample_name,
+                superset_sample.addresses.len(),
             );
-            let node_count = superset_sample.superset.len();
+            let node_count = superset_sample.addresses.len();
 
-            if node_count > 5000000 {
+            if node_count > 5000000 && graph_config.window_size.is_none() {
                 info!(
-                    "{:>150}: too much nodes, skipping, it will explode later down the line",
-                    sample_path.display()
+                    "{:>150}: too much nodes, skipping, it will explode later down the line (use --window-size to split it)",
+                    sample_name
                 );
                 return Ok(());
             }
 
-            let graph = superset_sample.into_graph();
-            let edges_count = graph.graph.edges.len();
-
-            let output_path = args
-                .graphs_out_path
-                .join(sample_path.strip_prefix(&args.samples_path).unwrap())
-                .with_extension("graph");
-
-            std::fs::create_dir_all(output_path.parent().unwrap())?;
-
-            let file = std::fs::File::create(&output_path)?;
-            let file = BufWriter::new(file);
-            graph.to_npz(&vocab, file)?;
+            let mut edges_count = 0;
+            let mut shard_count = 0;
+            let mut stats = DataDependencyStats::default();
+            for (i, graph) in GraphSample::new_windowed(superset_sample, &graph_config).enumerate() {
+                edges_count += graph.graph.edges.len();
+                shard_count += 1;
+                stats += graph.data_dependency_stats;
+
+                // the windows of a sample are numbered in the address order
+                let output_path = args.graphs_out_path.join(format!("{}.graph", sample_name));
+                let output_path = match graph.window {
+                    Some(_) => window_output_path(&output_path, i),
+                    None => output_path,
+                };
+
+                std::fs::create_dir_all(output_path.parent().unwrap())?;
+
+                let file = std::fs::File::create(&output_path)?;
+                let file = BufWriter::new(file);
+                graph.to_npz(&vocab, &sample_hash, file)?;
+            }
 
             let time = start.elapsed();
 
             info!(
-                "{:>150}: {:07} nodes {:09} edges in {:03.04}s",
-                sample_path.display(),
+                "{:>150}: {:07} nodes {:09} edges in {} graphs in {:03.04}s, {} data dependencies ({} + {} definitions pruned by count + distance)",
+                sample_name,
                 node_count,
                 edges_count,
+                shard_count,
                 time.as_secs_f64(),
+                stats.dependencies,
+                stats.dropped_by_count,
+                stats.dropped_by_distance,
             );
 
             Ok(())
@@ -1,7 +1,7 @@
-use crate::cli::util::collect_sample_paths;
+use crate::cli::util::SampleStore;
 use crate::disassembly::{DisasmToolConfig, DisasmToolName, ExecutableDisassembler};
 use crate::evaluate;
-use crate::model::ExecutableSample;
+use crate::model::{ExecutableSample, SourceKind};
 use anyhow::Context;
 use indicatif::ProgressIterator;
 use prettytable::{row, Table};
@@ -18,6 +18,9 @@ pub struct RunDisasmTool {
     sample_path: PathBuf,
     #[clap(short, long)]
     output_path: Option<PathBuf>,
+    /// Print every false positive and false negative along with the ground truth around it
+    #[clap(long)]
+    explain_errors: bool,
 }
 
 #[derive(Debug, clap::Args)]
@@ -29,6 +32,7 @@ pub struct RunDisasmTools {
 
 #[derive(Debug, clap::Args)]
 pub struct Evaluate {
+    /// Directory with `.sample` files or a sample archive
     samples_path: PathBuf,
     csv_path: PathBuf,
 }
@@ -63,11 +67,28 @@ pub async fn action_run_disasm_tool(args: RunDisasmTool) -> anyhow::Result<()> {
         writeln!(output, "0x{:x}", instr_addr).context("Writing to output")?;
     }
 
-    let superset = sample.into_superset();
+    let superset = sample.clone().into_superset();
 
     let eval = evaluate::evaluate_result(&superset, &result);
     let eval_summary = eval.summary();
 
+    if args.explain_errors {
+        for &address in eval.false_positives.iter() {
+            println!(
+                "0x{:08x} false positive ({})",
+                address,
+                sample.classes.describe(address)
+            );
+        }
+        for &address in eval.false_negatives.iter() {
+            println!(
+                "0x{:08x} false negative ({})",
+                address,
+                sample.classes.describe(address)
+            );
+        }
+    }
+
     println!("{:#?}", eval_summary);
 
     Ok(())
@@ -86,9 +107,12 @@ pub async fn action_run_disasm_tools(args: RunDisasmTools) -> anyhow::Result<()>
         "True Positives",
         "False Positives",
         "False Negatives",
+        "Ignored",
         "Precision",
         "Recall",
         "F1",
+        "Func Precision",
+        "Func Recall",
         "Time",
     ]);
 
@@ -118,9 +142,14 @@ pub async fn action_run_disasm_tools(args: RunDisasmTools) -> anyhow::Result<()>
             s.true_positives,
             s.false_positives,
             s.false_negatives,
+            s.ignored,
             format!("{:.05}", s.precision),
             format!("{:.05}", s.recall),
             format!("{:.05}", s.f1),
+            s.function_precision
+                .map_or("-".to_string(), |v| format!("{:.05}", v)),
+            s.function_recall
+                .map_or("-".to_string(), |v| format!("{:.05}", v)),
             format!("{:.02}s", time.as_secs_f64())
         ]);
     }
@@ -131,7 +160,8 @@ pub async fn action_run_disasm_tools(args: RunDisasmTools) -> anyhow::Result<()>
 }
 
 pub async fn action_evaluate(args: Evaluate) -> anyhow::Result<()> {
-    let samples = collect_sample_paths(&args.samples_path)?;
+    let store = SampleStore::open(&args.samples_path)?;
+    let samples = store.names();
     info!("Found {} samples", samples.len());
 
     let config = load_runner_config()?;
@@ -140,32 +170,28 @@ pub async fn action_evaluate(args: Evaluate) -> anyhow::Result<()> {
     struct CsvRecord {
         tool: DisasmToolName,
         sample: String,
+        source_kind: Option<SourceKind>,
+        package: Option<String>,
+        package_version: Option<String>,
+        producer: Option<String>,
         size: u64,
         true_positives: usize,
         false_positives: usize,
         false_negatives: usize,
+        ignored: usize,
         precision: f64,
         recall: f64,
         f1: f64,
+        function_precision: Option<f64>,
+        function_recall: Option<f64>,
         time: f64,
     }
 
     let mut csv = csv::Writer::from_path(&args.csv_path).context("Creating output CSV")?;
 
-    let path_prefix = args.samples_path.to_str().unwrap();
-
-    for (i, sample_path) in samples.iter().enumerate() {
-        let sample_name = sample_path
-            .to_str()
-            .unwrap()
-            .strip_prefix(path_prefix)
-            .unwrap()
-            .strip_prefix("/")
-            .unwrap()
-            .strip_suffix(".sample")
-            .unwrap();
-
-        let sample = ExecutableSample::deserialize_from(&mut File::open(&sample_path)?)?;
+    for (i, sample_name) in samples.iter().enumerate() {
+        let sample_name = sample_name.as_str();
+        let sample = store.load(sample_name)?;
         let superset = sample.clone().into_superset();
 
         let sample_size = sample.size();
@@ -201,16 +227,24 @@ pub async fn action_evaluate(args: Evaluate) -> anyhow::Result<()> {
             let eval = evaluate::evaluate_result(&superset, &result);
             let s = eval.summary();
 
+            let source = sample.source.as_ref();
             let record = CsvRecord {
                 tool,
                 sample: sample_name.to_string(),
+                source_kind: source.map(|s| s.kind),
+                package: source.and_then(|s| s.package_name.clone()),
+                package_version: source.and_then(|s| s.package_version.clone()),
+                producer: source.and_then(|s| s.producer.clone()),
                 size: sample_size,
                 true_positives: s.true_positives,
                 false_positives: s.false_positives,
                 false_negatives: s.false_negatives,
+                ignored: s.ignored,
                 precision: s.precision,
                 recall: s.recall,
                 f1: s.f1,
+                function_precision: s.function_precision,
+                function_recall: s.function_recall,
                 time: time.as_secs_f64(),
             };
 
@@ -1,19 +1,33 @@
+mod archive;
+mod bench_graph;
 mod bulk_make_graph;
 mod evaluation;
 mod similarity;
 mod util;
+mod validate;
+mod verify;
 
+use archive::{PackSamples, UnpackSamples};
+use bench_graph::BenchGraph;
 use bulk_make_graph::BulkMakeGraph;
 use evaluation::{Evaluate, RunDisasmTool, RunDisasmTools};
 use similarity::{CheckSimilarity, SplitSamples};
+use validate::ValidateSample;
+use verify::VerifySamples;
 
 use crate::fetch;
-use crate::model::{CodeVocab, ExecutableSample};
+use crate::model::interval_set::IntervalSet;
+use crate::model::{
+    sample_format, CodeVocab, ExecutableSample, GraphConfig, GraphSample, RelationType,
+    SupersetConfig,
+};
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use itertools::Itertools;
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufReader, BufWriter};
 use std::path::PathBuf;
+use strum::IntoEnumIterator;
 
 #[derive(Debug, Parser)]
 pub struct Cli {
@@ -28,13 +42,19 @@ enum Action {
     SampleToStrippedElf(SampleToStrippedElf),
     MakeSuperset(MakeSuperset),
     MakeGraph(MakeGraph),
+    ShowGraph(ShowGraph),
     BulkMakeGraph(BulkMakeGraph),
+    BenchGraph(BenchGraph),
     PythonCodegen,
     RunDisasmTool(RunDisasmTool),
     RunDisasmTools(RunDisasmTools),
     Evaluate(Evaluate),
     CheckSimilarity(CheckSimilarity),
     SplitSamples(SplitSamples),
+    VerifySamples(VerifySamples),
+    PackSamples(PackSamples),
+    UnpackSamples(UnpackSamples),
+    ValidateSample(ValidateSample),
 }
 
 #[derive(Debug, clap::Args)]
@@ -62,6 +82,8 @@ struct SampleToStrippedElf {
 struct MakeSuperset {
     sample_path: PathBuf,
     output_path: PathBuf,
+    #[clap(flatten)]
+    superset: SupersetConfig,
 }
 
 #[derive(Debug, clap::Args)]
@@ -69,6 +91,21 @@ struct MakeGraph {
     sample_path: PathBuf,
     vocab_path: PathBuf,
     output_path: PathBuf,
+    #[clap(flatten)]
+    superset: SupersetConfig,
+    #[clap(flatten)]
+    graph: GraphConfig,
+    /// YAML file with the graph config, overrides the graph flags
+    #[clap(long)]
+    graph_config: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+struct ShowGraph {
+    graph_path: PathBuf,
+    /// Check that the graph was made with this vocab
+    #[clap(long)]
+    vocab_path: Option<PathBuf>,
 }
 
 impl Cli {
@@ -79,13 +116,19 @@ impl Cli {
             Action::SampleToStrippedElf(args) => action_sample_to_stripped_elf(args).await,
             Action::MakeSuperset(args) => action_make_superset(args).await,
             Action::MakeGraph(args) => action_make_graph(args).await,
+            Action::ShowGraph(args) => action_show_graph(args).await,
             Action::BulkMakeGraph(args) => bulk_make_graph::action_bulk_make_graph(args).await,
+            Action::BenchGraph(args) => bench_graph::action_bench_graph(args).await,
             Action::PythonCodegen => action_python_codegen().await,
             Action::RunDisasmTool(args) => evaluation::action_run_disasm_tool(args).await,
             Action::RunDisasmTools(args) => evaluation::action_run_disasm_tools(args).await,
             Action::Evaluate(args) => evaluation::action_evaluate(args).await,
             Action::CheckSimilarity(args) => similarity::action_check_similarity(args).await,
             Action::SplitSamples(args) => similarity::action_split_samples(args).await,
+            Action::VerifySamples(args) => verify::action_verify_samples(args).await,
+            Action::PackSamples(args) => archive::action_pack_samples(args).await,
+            Action::UnpackSamples(args) => archive::action_unpack_samples(args).await,
+            Action::ValidateSample(args) => validate::action_validate_sample(args).await,
         }
     }
 }
@@ -107,6 +150,15 @@ async fn action_sync_data(args: SyncData) -> Result<()> {
 async fn action_show_sample(args: ShowSample) -> Result<()> {
     let sample = ExecutableSample::deserialize_from(&mut File::open(&args.sample_path)?)?;
 
+    let header = sample_format::deserialize_header_from(&mut File::open(&args.sample_path)?)?;
+    println!("Format: {}", header);
+    println!("Architecture: {}", sample.architecture);
+
+    if let Some(source) = &sample.source {
+        println!("Source:");
+        print!("{}", source);
+    }
+
     println!("Memory map:");
     println!("{}", sample.memory.map());
 
@@ -124,6 +176,37 @@ async fn action_show_sample(args: ShowSample) -> Result<()> {
         coverage_float * 100.0
     );
 
+    let labeled = sample
+        .classes
+        .true_instructions
+        .union(&sample.classes.true_data);
+    let gaps = labeled.gaps().collect::<IntervalSet<_>>();
+    if !gaps.is_empty() {
+        println!(
+            "Unlabeled gaps: {} bytes in {} gaps",
+            gaps.total_len(),
+            gaps.iter().count()
+        );
+    }
+
+    if sample.classes.symbols.is_empty() {
+        println!("Symbols: none");
+    } else {
+        println!("Symbols: {}", sample.classes.symbols.iter().count());
+    }
+
+    if !sample.sections.is_empty() {
+        println!("Sections:");
+        for (interval, name) in sample.sections.iter() {
+            println!(
+                "  {:>16}: {:08x}-{:08x}",
+                name,
+                interval.start(),
+                interval.end()
+            );
+        }
+    }
+
     Ok(())
 }
 
@@ -141,24 +224,105 @@ async fn action_sample_to_stripped_elf(args: SampleToStrippedElf) -> Result<()>
 
 async fn action_make_superset(args: MakeSuperset) -> Result<()> {
     let sample = ExecutableSample::deserialize_from(&mut File::open(&args.sample_path)?)?;
-    let superset = sample.into_superset();
+    let sample_hash = sample.hash();
+    let superset = sample.into_superset_with_config(&args.superset);
 
     let file = File::create(&args.output_path)?;
     let file = BufWriter::new(file);
-    superset.to_parquet(file)?;
+    superset.to_parquet(&sample_hash, file)?;
 
     Ok(())
 }
 
 async fn action_make_graph(args: MakeGraph) -> Result<()> {
     let sample = ExecutableSample::deserialize_from(&mut File::open(&args.sample_path)?)?;
-    let graph = sample.into_graph();
+    let config = util::load_graph_config(args.graph, args.graph_config.as_deref())?;
+    let sample_hash = sample.hash();
+    let superset = sample.into_superset_with_config(&args.superset);
 
     let vocab = CodeVocab::deserialize_from(File::open(&args.vocab_path)?)?;
 
-    let file = File::create(&args.output_path)?;
-    let file = BufWriter::new(file);
-    graph.to_npz(&vocab, file)?;
+    // the windows of the sample are numbered in the address order, like in bulk-make-graph
+    for (i, graph) in GraphSample::new_windowed(superset, &config).enumerate() {
+        let output_path = match graph.window {
+            Some(_) => util::window_output_path(&args.output_path, i),
+            None => args.output_path.clone(),
+        };
+        let file = File::create(&output_path)
+            .with_context(|| format!("Creating graph file {}", output_path.display()))?;
+        let file = BufWriter::new(file);
+        graph.to_npz(&vocab, &sample_hash, file)?;
+    }
+
+    Ok(())
+}
+
+async fn action_show_graph(args: ShowGraph) -> Result<()> {
+    let graph = GraphSample::from_npz(BufReader::new(File::open(&args.graph_path)?))
+        .with_context(|| format!("Reading graph {}", args.graph_path.display()))?;
+    let metadata = &graph.metadata;
+
+    println!("Sample hash: {}", metadata.sample_hash);
+    println!("Vocab hash: {}", metadata.vocab_hash);
+    if let Some(vocab_path) = &args.vocab_path {
+        let vocab = CodeVocab::deserialize_from(File::open(vocab_path)?)?;
+        if vocab.hash() == metadata.vocab_hash {
+            println!("Vocab matches");
+        } else {
+            println!("Vocab DOES NOT match: {}", vocab.hash());
+        }
+    }
+    println!(
+        "Superset config: {}",
+        serde_json::to_string(&metadata.superset_config)?
+    );
+    println!(
+        "Graph config: {}",
+        serde_json::to_string(&metadata.graph_config)?
+    );
+    if let Some(window) = &metadata.window {
+        println!(
+            "Window: {:08x}-{:08x} (halo {:#x})",
+            window.start, window.end, window.halo
+        );
+    }
+
+    let nodes = graph.node_addresses.len();
+    let unknown = graph
+        .instruction_codes
+        .iter()
+        .filter(|&&c| c as usize == CodeVocab::UNKNOWN)
+        .count();
+    let sizes = graph
+        .instruction_sizes
+        .iter()
+        .map(|&s| s as usize)
+        .sum::<usize>();
+    println!(
+        "Nodes: {} ({} with codes not in the vocab, {:.2} bytes on average)",
+        nodes,
+        unknown,
+        sizes as f64 / nodes.max(1) as f64
+    );
+    println!(
+        "Function starts: {}",
+        graph.is_function_start.iter().filter(|&&v| v).count()
+    );
+    if let (Some(first), Some(last)) = (graph.node_addresses.first(), graph.node_addresses.last()) {
+        println!("Addresses: {:08x}-{:08x}", first, last);
+    }
+    if let Some(labels) = &graph.labels {
+        let code = labels.iter().filter(|l| l.is_code()).count();
+        println!("Labels: {} code, {} not code", code, labels.len() - code);
+    }
+
+    println!("Relations: {}", graph.graph.edges.len());
+    let counts = graph.graph.edge_types.iter().counts();
+    for relation in RelationType::iter() {
+        if let Some(count) = counts.get(&relation) {
+            println!("  {:>16}: {}", <&str>::from(relation), count);
+        }
+    }
 
     Ok(())
 }
@@ -1,4 +1,4 @@
-use crate::cli::util::collect_sample_paths;
+use crate::cli::util::SampleStore;
 use crate::model::ExecutableSample;
 use crate::split::{NGramIndex, SplitBuilder};
 use indicatif::{ParallelProgressIterator, ProgressIterator};
@@ -6,6 +6,7 @@ use itertools::Itertools;
 use ndarray::Array;
 use owo_colors::{OwoColorize, Style};
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
@@ -19,9 +20,13 @@ pub struct CheckSimilarity {
 
 #[derive(Debug, clap::Args)]
 pub struct SplitSamples {
+    /// Directory with `.sample` files or a sample archive
     samples_path: PathBuf,
     #[clap(short, long, default_value_t = 0.2)]
     test_proportion: f64,
+    /// Put samples from the same package into the same split, regardless of their similarity
+    #[clap(long)]
+    group_by_package: bool,
     labels_out_path: PathBuf,
 }
 
@@ -77,33 +82,36 @@ pub async fn action_check_similarity(args: CheckSimilarity) -> anyhow::Result<()
 pub async fn action_split_samples(args: SplitSamples) -> anyhow::Result<()> {
     use petgraph::prelude::*;
 
-    let sample_paths = collect_sample_paths(&args.samples_path)?;
+    let store = SampleStore::open(&args.samples_path)?;
+    let sample_names = store.names();
 
-    info!("Found {} samples", sample_paths.len());
+    info!("Found {} samples", sample_names.len());
 
     const NGRAMS_N: usize = 4;
 
     info!("Loading samples...");
-    let samples = sample_paths
+    let samples = sample_names
         .par_iter()
         .progress()
-        .map(|p| {
-            File::open(p)
-                .map_err(anyhow::Error::from)
-                .and_then(|mut f| ExecutableSample::deserialize_from(&mut f))
-                .map(|sample| {
-                    let size = sample.size();
-                    let ngrams = NGramIndex::<NGRAMS_N>::new(&sample.memory);
-
-                    (size, ngrams)
-                })
+        .map(|name| {
+            store.load(name).map(|sample| {
+                let size = sample.size();
+                let ngrams = NGramIndex::<NGRAMS_N>::new(&sample.memory);
+                let package = sample.source.as_ref().and_then(|s| s.package_key());
+
+                (size, ngrams, package)
+            })
         })
         .collect::<anyhow::Result<Vec<_>>>()?;
 
-    let sample_sizes = samples.iter().map(|(size, _)| *size).collect::<Vec<_>>();
+    let sample_sizes = samples.iter().map(|(size, _, _)| *size).collect::<Vec<_>>();
+    let sample_packages = samples
+        .iter()
+        .map(|(_, _, package)| package.clone())
+        .collect::<Vec<_>>();
     let samples_ngrams = samples
         .into_iter()
-        .map(|(_, ngrams)| ngrams)
+        .map(|(_, ngrams, _)| ngrams)
         .collect::<Vec<_>>();
 
     info!("Computing similarity matrix...");
@@ -144,6 +152,16 @@ pub async fn action_split_samples(args: SplitSamples) -> anyhow::Result<()> {
         }
     }
 
+    if args.group_by_package {
+        let mut package_representatives = HashMap::new();
+        for (i, package) in sample_packages.iter().enumerate() {
+            if let Some(package) = package {
+                let representative = *package_representatives.entry(package).or_insert(i);
+                graph.add_edge(NodeIndex::new(representative), NodeIndex::new(i), ());
+            }
+        }
+    }
+
     let scc = petgraph::algo::tarjan_scc(&graph);
     let scc = scc
         .into_iter()
@@ -170,21 +188,9 @@ pub async fn action_split_samples(args: SplitSamples) -> anyhow::Result<()> {
         train.target_fraction, test.target_fraction, train.actual_fraction, test.actual_fraction
     );
 
-    let path_prefix = args.samples_path.to_str().unwrap();
-
     let mut output = File::create(&args.labels_out_path)?;
     for (group, group_name) in split.iter().zip(&["train", "test"]) {
-        for sample_path in group.items.iter().map(|&i| &sample_paths[i]).sorted() {
-            let sample_name = sample_path
-                .to_str()
-                .unwrap()
-                .strip_prefix(path_prefix)
-                .unwrap()
-                .strip_prefix("/")
-                .unwrap()
-                .strip_suffix(".sample")
-                .unwrap();
-
+        for sample_name in group.items.iter().map(|&i| &sample_names[i]).sorted() {
             writeln!(output, "{} {}", group_name, sample_name)?;
         }
     }
@@ -1,5 +1,34 @@
+use crate::model::archive::SampleArchive;
+use crate::model::{ExecutableSample, GraphConfig};
+use anyhow::Context;
 use std::path::{Path, PathBuf};
 
+/// The graph config from the YAML file at `path` if given, otherwise the one from the flags
+pub fn load_graph_config(flags: GraphConfig, path: Option<&Path>) -> anyhow::Result<GraphConfig> {
+    let config = match path {
+        Some(path) => {
+            let config = std::fs::read_to_string(path)
+                .with_context(|| format!("Reading graph config file {}", path.display()))?;
+            serde_yaml::from_str(&config)
+                .with_context(|| format!("Parsing graph config file {}", path.display()))?
+        }
+        None => flags,
+    };
+    anyhow::ensure!(
+        config.window_size != Some(0),
+        "The window size must be positive"
+    );
+    Ok(config)
+}
+
+/// Where the `i`-th window of a graph is written, `ls.graph` becomes `ls.0003.graph`
+pub fn window_output_path(path: &Path, i: usize) -> PathBuf {
+    match path.extension() {
+        Some(extension) => path.with_extension(format!("{:04}.{}", i, extension.to_string_lossy())),
+        None => path.with_extension(format!("{:04}", i)),
+    }
+}
+
 pub fn collect_sample_paths(samples_path: &Path) -> anyhow::Result<Vec<PathBuf>> {
     walkdir::WalkDir::new(samples_path)
         .into_iter()
@@ -11,3 +40,102 @@ pub fn collect_sample_paths(samples_path: &Path) -> anyhow::Result<Vec<PathBuf>>
         .map(|r| r.map(|e| e.into_path()).map_err(|e| e.into()))
         .collect::<anyhow::Result<Vec<PathBuf>>>()
 }
+
+/// A collection of samples: either a directory tree of `.sample` files or a sample archive
+///
+/// A single `.sample` file is treated as a directory with just one sample
+///
+/// The samples are identified by their name: path relative to the directory without the `.sample` extension
+/// (or the name of the entry in the archive)
+pub enum SampleStore {
+    Directory { root: PathBuf, names: Vec<String> },
+    Archive(SampleArchive),
+}
+
+impl SampleStore {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if path.is_file() && path.extension().unwrap_or_default() == "sample" {
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+
+            Ok(SampleStore::Directory {
+                root: path.parent().unwrap().to_path_buf(),
+                names: vec![name],
+            })
+        } else if path.is_dir() {
+            let mut names = collect_sample_paths(path)?
+                .into_iter()
+                .map(|sample_path| {
+                    sample_path
+                        .strip_prefix(path)
+                        .unwrap()
+                        .with_extension("")
+                        .to_str()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>();
+            names.sort();
+
+            Ok(SampleStore::Directory {
+                root: path.to_path_buf(),
+                names,
+            })
+        } else {
+            Ok(SampleStore::Archive(SampleArchive::open(path)?))
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        match self {
+            SampleStore::Directory { names, .. } => names.clone(),
+            SampleStore::Archive(archive) => archive.names().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    /// Reads the `.sample` file without decoding it
+    pub fn read_raw(&self, name: &str) -> anyhow::Result<Vec<u8>> {
+        match self {
+            SampleStore::Directory { root, .. } => {
+                Ok(std::fs::read(root.join(format!("{}.sample", name)))?)
+            }
+            SampleStore::Archive(archive) => archive.read_raw(name),
+        }
+    }
+
+    pub fn load(&self, name: &str) -> anyhow::Result<ExecutableSample> {
+        match self {
+            SampleStore::Directory { .. } => {
+                ExecutableSample::deserialize_from(&mut self.read_raw(name)?.as_slice())
+            }
+            SampleStore::Archive(archive) => archive.read(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_graph_config() {
+        let config = load_graph_config(GraphConfig::default(), None).unwrap();
+        assert_eq!(config, GraphConfig::default());
+        let zero_window = GraphConfig {
+            window_size: Some(0),
+            ..Default::default()
+        };
+        assert!(load_graph_config(zero_window, None).is_err());
+    }
+
+    #[test]
+    fn test_window_output_path() {
+        assert_eq!(
+            window_output_path(Path::new("out/ls.graph"), 3),
+            Path::new("out/ls.0003.graph")
+        );
+        assert_eq!(
+            window_output_path(Path::new("ls"), 12),
+            Path::new("ls.0012")
+        );
+    }
+}
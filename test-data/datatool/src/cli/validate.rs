@@ -0,0 +1,108 @@
+use crate::cli::util::SampleStore;
+use crate::model::validate::{validate_sample, ValidationReport};
+use indicatif::ParallelProgressIterator;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug, clap::Args)]
+pub struct ValidateSample {
+    /// A `.sample` file, a directory with `.sample` files or a sample archive
+    samples_path: PathBuf,
+    /// Write the reports as JSON lines to this file
+    #[clap(short, long)]
+    report_out_path: Option<PathBuf>,
+    /// Write the names of the samples with inconsistent ground truth to this file (one per line)
+    #[clap(short, long)]
+    quarantine_out_path: Option<PathBuf>,
+    /// How many issues to print for each sample
+    #[clap(long, default_value_t = 10)]
+    max_printed_issues: usize,
+}
+
+pub async fn action_validate_sample(args: ValidateSample) -> anyhow::Result<()> {
+    let store = SampleStore::open(&args.samples_path)?;
+    let samples = store.names();
+    info!("Found {} samples", samples.len());
+
+    let reports = samples
+        .par_iter()
+        .progress_count(samples.len() as u64)
+        .map(|sample_name| -> anyhow::Result<_> {
+            let sample = store.load(sample_name)?;
+            Ok((sample_name, validate_sample(&sample)))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut issue_counts = BTreeMap::<&str, usize>::new();
+    for (sample_name, report) in reports.iter() {
+        if report.is_ok() {
+            println!("{}: OK", sample_name);
+            continue;
+        }
+
+        println!("{}: {} issues", sample_name, report.issues.len());
+        for issue in report.issues.iter().take(args.max_printed_issues) {
+            println!("    {}", issue);
+        }
+        if report.issues.len() > args.max_printed_issues {
+            println!(
+                "    ... and {} more",
+                report.issues.len() - args.max_printed_issues
+            );
+        }
+
+        for issue in report.issues.iter() {
+            *issue_counts.entry(issue.kind()).or_insert(0) += 1;
+        }
+    }
+
+    if let Some(report_out_path) = &args.report_out_path {
+        #[derive(Serialize)]
+        struct JsonRecord<'a> {
+            sample: &'a str,
+            #[serde(flatten)]
+            report: &'a ValidationReport,
+        }
+
+        let mut output = BufWriter::new(File::create(report_out_path)?);
+        for (sample_name, report) in reports.iter() {
+            serde_json::to_writer(
+                &mut output,
+                &JsonRecord {
+                    sample: sample_name,
+                    report,
+                },
+            )?;
+            writeln!(output)?;
+        }
+    }
+
+    let quarantined = reports
+        .iter()
+        .filter(|(_, report)| !report.is_ok())
+        .map(|(sample_name, _)| sample_name)
+        .collect::<Vec<_>>();
+
+    if let Some(quarantine_out_path) = &args.quarantine_out_path {
+        let mut output = File::create(quarantine_out_path)?;
+        for sample_name in quarantined.iter() {
+            writeln!(output, "{}", sample_name)?;
+        }
+    }
+
+    for (kind, count) in issue_counts.iter() {
+        println!("{}: {}", kind, count);
+    }
+    println!(
+        "{} of {} samples have inconsistent ground truth",
+        quarantined.len(),
+        samples.len()
+    );
+
+    Ok(())
+}
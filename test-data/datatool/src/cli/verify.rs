@@ -0,0 +1,76 @@
+use crate::cli::util::SampleStore;
+use crate::model::sample_format::{self, SampleHeader};
+use crate::model::ExecutableSample;
+use anyhow::Context;
+use indicatif::ParallelProgressIterator;
+use itertools::Itertools;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug, clap::Args)]
+pub struct VerifySamples {
+    /// Directory with `.sample` files or a sample archive
+    samples_path: PathBuf,
+    /// Write the names of the unreadable samples to this file (one per line)
+    #[clap(short, long)]
+    unreadable_out_path: Option<PathBuf>,
+}
+
+fn verify_sample(store: &SampleStore, name: &str) -> anyhow::Result<SampleHeader> {
+    let data = store.read_raw(name)?;
+    let header = sample_format::deserialize_header_from(&mut data.as_slice())
+        .context("Reading the header")?;
+    ExecutableSample::deserialize_from(&mut data.as_slice()).context("Decoding the sample")?;
+    Ok(header)
+}
+
+pub async fn action_verify_samples(args: VerifySamples) -> anyhow::Result<()> {
+    let store = SampleStore::open(&args.samples_path)?;
+    let samples = store.names();
+    info!("Found {} samples", samples.len());
+
+    let results = samples
+        .par_iter()
+        .progress_count(samples.len() as u64)
+        .map(|sample_name| (sample_name, verify_sample(&store, sample_name)))
+        .collect::<Vec<_>>();
+
+    let mut versions = BTreeMap::new();
+    let mut unreadable = Vec::new();
+    for (sample_name, result) in results {
+        match result {
+            Ok(header) => *versions.entry(header.version).or_insert(0usize) += 1,
+            Err(e) => unreadable.push((sample_name, e)),
+        }
+    }
+
+    for (version, count) in versions.iter() {
+        println!("format v{}: {} samples", version, count);
+    }
+
+    if let Some(out_path) = &args.unreadable_out_path {
+        let mut output = File::create(out_path)?;
+        for (sample_name, _) in unreadable.iter() {
+            writeln!(output, "{}", sample_name)?;
+        }
+    }
+
+    if !unreadable.is_empty() {
+        for (sample_name, e) in unreadable.iter().sorted_by_key(|(p, _)| *p) {
+            println!("UNREADABLE {}: {:#}", sample_name, e);
+        }
+        anyhow::bail!(
+            "{} of {} samples are unreadable",
+            unreadable.len(),
+            samples.len()
+        );
+    }
+
+    println!("All {} samples are readable", samples.len());
+
+    Ok(())
+}
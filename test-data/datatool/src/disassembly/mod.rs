@@ -11,6 +11,8 @@ pub use tool::{DeepDiConfig, DisasmToolConfig, DisasmToolName, DisasmToolWithCon
 #[derive(Serialize, Deserialize)]
 pub struct DisassemblyResult {
     pub predicted_instructions: BTreeSet<u32>,
+    /// Function entry points, for the tools that find them
+    pub predicted_functions: Option<BTreeSet<u32>>,
 }
 
 #[async_trait]
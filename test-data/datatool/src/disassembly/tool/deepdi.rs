@@ -117,5 +117,6 @@ pub async fn run_deepdi(
 
     Ok(DisassemblyResult {
         predicted_instructions,
+        predicted_functions: None,
     })
 }
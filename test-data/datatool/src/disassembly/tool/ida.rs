@@ -42,12 +42,21 @@ static LST_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"\w+:(?P<addr>[0-9A-F]+)(?: (?:[0-9A-F]{2}[ +]+)+(?P<content>.*))?").unwrap()
 });
 
-fn parse_lst(lst: &str) -> Result<BTreeSet<u32>> {
+static PROC_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\w+:(?P<addr>[0-9A-F]+)\s+\S+\s+proc\s+(?:near|far)\b").unwrap());
+
+fn parse_lst(lst: &str) -> Result<(BTreeSet<u32>, BTreeSet<u32>)> {
     let mut result = BTreeSet::new();
+    let mut functions = BTreeSet::new();
 
     let mut prev_processed_addr = 0u32;
 
     for line in lst.lines() {
+        if let Some(captures) = PROC_REGEX.captures(line) {
+            functions.insert(u32::from_str_radix(&captures["addr"], 16).unwrap());
+            continue;
+        }
+
         if let Some(captures) = LST_REGEX.captures(line) {
             let addr = u32::from_str_radix(&captures["addr"], 16).unwrap();
             if prev_processed_addr == addr {
@@ -72,7 +81,7 @@ fn parse_lst(lst: &str) -> Result<BTreeSet<u32>> {
         }
     }
 
-    Ok(result)
+    Ok((result, functions))
 }
 
 pub async fn run_ida(config: &IdaConfig, sample: &ExecutableSample) -> Result<DisassemblyResult> {
@@ -119,9 +128,10 @@ pub async fn run_ida(config: &IdaConfig, sample: &ExecutableSample) -> Result<Di
 
     let lst = std::fs::read_to_string(&lst_path).context("Failed to read IDA output")?;
 
-    let predicted_instructions = parse_lst(&lst)?;
+    let (predicted_instructions, predicted_functions) = parse_lst(&lst)?;
 
     Ok(DisassemblyResult {
         predicted_instructions,
+        predicted_functions: Some(predicted_functions),
     })
 }
@@ -1,5 +1,7 @@
 use crate::disassembly::DisassemblyResult;
-use crate::model::{CodeVocab, ExecutableSample};
+use crate::model::{
+    CodeVocab, ExecutableSample, GraphConfig, GraphSample, SupersetConfig, SupersetSample,
+};
 use anyhow::{Context, Result};
 use futures_util::StreamExt;
 use serde::Deserialize;
@@ -17,29 +19,25 @@ pub struct IdentifyX86Config {
     pub model_path: String,
     pub code_vocab_path: String,
     pub image_name: String,
+    /// Should match the config the model was trained on
+    #[serde(default)]
+    pub superset: SupersetConfig,
+    #[serde(default)]
+    pub graph: GraphConfig,
 }
 
-pub async fn run_identify_x86(
+/// Runs the model on a single graph, returning the indices of the nodes it predicts to be instructions
+async fn predict_nodes(
+    docker: &Docker,
     config: &IdentifyX86Config,
-    sample: &ExecutableSample,
-) -> Result<DisassemblyResult> {
-    debug!("Running IdentifyX86");
-
-    let docker = Docker::new();
-
-    debug!("Computing the graph");
-    let sample_superset = sample.clone().into_superset();
-    let sample_graph = sample_superset.clone().into_graph();
-
-    let code_vocab = CodeVocab::deserialize_from(
-        File::open(&config.code_vocab_path)
-            .with_context(|| format!("Opening code vocab file {}", config.code_vocab_path))?,
-    )?;
-
+    code_vocab: &CodeVocab,
+    sample_hash: &str,
+    sample_graph: GraphSample,
+) -> Result<Vec<u32>> {
     let mut sample_graph_file =
         tempfile::NamedTempFile::new().context("Failed to create temporary file")?;
     sample_graph
-        .to_npz(&code_vocab, &mut sample_graph_file)
+        .to_npz(code_vocab, sample_hash, &mut sample_graph_file)
         .context("Failed to write graph")?;
 
     sample_graph_file
@@ -119,7 +117,7 @@ pub async fn run_identify_x86(
     let output =
         std::str::from_utf8(&output).context("Failed to parse identify-x86 output as string")?;
 
-    let mut predicted_nodes = BTreeSet::new();
+    let mut predicted_nodes = Vec::new();
 
     for line in output.lines() {
         let line = line.trim();
@@ -128,15 +126,52 @@ pub async fn run_identify_x86(
         }
 
         let address = u32::from_str(line).context("Failed to parse node index")?;
-        predicted_nodes.insert(address);
+        predicted_nodes.push(address);
     }
 
-    let predicted_instructions = predicted_nodes
-        .into_iter()
-        .map(|v| sample_superset.superset[v as usize].0)
-        .collect();
+    Ok(predicted_nodes)
+}
+
+pub async fn run_identify_x86(
+    config: &IdentifyX86Config,
+    sample: &ExecutableSample,
+) -> Result<DisassemblyResult> {
+    debug!("Running IdentifyX86");
+
+    let docker = Docker::new();
+
+    let sample_hash = sample.hash();
+    let sample_superset = SupersetSample::with_config(sample, &config.superset);
+
+    let code_vocab = CodeVocab::deserialize_from(
+        File::open(&config.code_vocab_path)
+            .with_context(|| format!("Opening code vocab file {}", config.code_vocab_path))?,
+    )?;
+
+    debug!("Computing the graph");
+    // the big samples are split into windows, each node is predicted by the window having it in the core
+    let mut predicted_instructions = BTreeSet::new();
+    for sample_graph in GraphSample::new_windowed(sample_superset, &config.graph) {
+        let addresses = sample_graph.addresses.clone();
+        let is_core = sample_graph.window.as_ref().map(|w| w.is_core.clone());
+        let nodes = predict_nodes(&docker, config, &code_vocab, &sample_hash, sample_graph).await?;
+        for node in nodes {
+            let node = node as usize;
+            let &address = addresses
+                .get(node)
+                .with_context(|| format!("The model predicted a non-existent node {}", node))?;
+            if is_core
+                .as_ref()
+                .map(|is_core| is_core[node])
+                .unwrap_or(true)
+            {
+                predicted_instructions.insert(address);
+            }
+        }
+    }
 
     Ok(DisassemblyResult {
         predicted_instructions,
+        predicted_functions: None,
     })
 }
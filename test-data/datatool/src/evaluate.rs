@@ -1,5 +1,5 @@
 use crate::disassembly::DisassemblyResult;
-use crate::model::{Label, SupersetSample};
+use crate::model::SupersetSample;
 use std::collections::BTreeSet;
 
 #[derive(Debug, Default)]
@@ -7,6 +7,29 @@ pub struct EvaluationResult {
     pub true_positives: BTreeSet<u32>,
     pub false_positives: BTreeSet<u32>,
     pub false_negatives: BTreeSet<u32>,
+    /// Predictions at the addresses that are not scored (padding and unlabeled bytes)
+    pub ignored: BTreeSet<u32>,
+    /// Only available if the disassembler reports the functions
+    pub functions: Option<FunctionEvaluationResult>,
+}
+
+#[derive(Debug, Default)]
+pub struct FunctionEvaluationResult {
+    pub true_positives: BTreeSet<u32>,
+    pub false_positives: BTreeSet<u32>,
+    pub false_negatives: BTreeSet<u32>,
+}
+
+impl FunctionEvaluationResult {
+    pub fn precision(&self) -> f64 {
+        let true_positives = self.true_positives.len();
+        true_positives as f64 / (true_positives + self.false_positives.len()) as f64
+    }
+
+    pub fn recall(&self) -> f64 {
+        let true_positives = self.true_positives.len();
+        true_positives as f64 / (true_positives + self.false_negatives.len()) as f64
+    }
 }
 
 impl EvaluationResult {
@@ -14,6 +37,7 @@ impl EvaluationResult {
         let true_positives = self.true_positives.len();
         let false_positives = self.false_positives.len();
         let false_negatives = self.false_negatives.len();
+        let ignored = self.ignored.len();
         let precision = true_positives as f64 / (true_positives + false_positives) as f64;
         let recall = true_positives as f64 / (true_positives + false_negatives) as f64;
         let f1 = 2.0 * precision * recall / (precision + recall);
@@ -21,9 +45,12 @@ impl EvaluationResult {
             true_positives,
             false_positives,
             false_negatives,
+            ignored,
             precision,
             recall,
             f1,
+            function_precision: self.functions.as_ref().map(|f| f.precision()),
+            function_recall: self.functions.as_ref().map(|f| f.recall()),
         }
     }
 }
@@ -33,28 +60,72 @@ pub struct EvaluationResultSummary {
     pub true_positives: usize,
     pub false_positives: usize,
     pub false_negatives: usize,
+    pub ignored: usize,
     pub precision: f64,
     pub recall: f64,
     pub f1: f64,
+    pub function_precision: Option<f64>,
+    pub function_recall: Option<f64>,
+}
+
+fn evaluate_functions(
+    superset: &SupersetSample,
+    predicted_functions: &BTreeSet<u32>,
+) -> FunctionEvaluationResult {
+    let true_functions = superset
+        .addresses
+        .iter()
+        .zip(superset.is_function_start.iter())
+        .filter(|(_, &is_function_start)| is_function_start)
+        .map(|(&address, _)| address)
+        .collect::<BTreeSet<_>>();
+
+    FunctionEvaluationResult {
+        true_positives: predicted_functions
+            .intersection(&true_functions)
+            .copied()
+            .collect(),
+        false_positives: predicted_functions
+            .difference(&true_functions)
+            .copied()
+            .collect(),
+        false_negatives: true_functions
+            .difference(predicted_functions)
+            .copied()
+            .collect(),
+    }
 }
 
 pub fn evaluate_result(superset: &SupersetSample, result: &DisassemblyResult) -> EvaluationResult {
+    // the samples converted from the formats without the function starts don't have any to compare with
+    let has_function_starts = superset.is_function_start.iter().any(|&v| v);
+    let functions = result
+        .predicted_functions
+        .as_ref()
+        .filter(|_| has_function_starts)
+        .map(|predicted_functions| evaluate_functions(superset, predicted_functions));
     let result = &result.predicted_instructions;
     let mut true_result = BTreeSet::new();
+    let mut unscored = BTreeSet::new();
 
-    for &(address, _, label) in superset.superset.iter() {
-        match label.unwrap() {
-            Label::Code => {
-                true_result.insert(address);
-            }
-            Label::NotCode => {}
+    for (&address, &label) in superset.addresses.iter().zip(superset.labels.iter()) {
+        let label = label.unwrap();
+        if label.is_code() {
+            true_result.insert(address);
+        } else if !label.is_scored() {
+            unscored.insert(address);
         }
     }
 
-    let mut evaluation_result = EvaluationResult::default();
+    let mut evaluation_result = EvaluationResult {
+        functions,
+        ..Default::default()
+    };
 
     for address in result.iter() {
-        if true_result.contains(address) {
+        if unscored.contains(address) {
+            evaluation_result.ignored.insert(*address);
+        } else if true_result.contains(address) {
             evaluation_result.true_positives.insert(*address);
         } else {
             evaluation_result.false_positives.insert(*address);
@@ -69,3 +140,70 @@ pub fn evaluate_result(superset: &SupersetSample, result: &DisassemblyResult) ->
 
     evaluation_result
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::interval_set::Interval;
+    use crate::model::{AddressClasses, ExecutableSample};
+    use memory_image::{MemoryImage, Protection};
+
+    fn superset(function_starts: &[u32]) -> SupersetSample {
+        let mut classes = AddressClasses::new();
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0x1000, 0x1004));
+        classes.function_starts.extend(function_starts);
+
+        let mut memory = MemoryImage::new();
+        // two `ret`s followed by two `nop`s
+        let code = vec![0xc3, 0xc3, 0x90, 0x90];
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+
+        ExecutableSample::new(memory, classes)
+            .unwrap()
+            .into_superset()
+    }
+
+    #[test]
+    fn test_is_function_start() {
+        let superset = superset(&[0x1000, 0x1001]);
+        assert_eq!(superset.addresses, vec![0x1000, 0x1001, 0x1002, 0x1003]);
+        assert_eq!(superset.is_function_start, vec![true, true, false, false]);
+    }
+
+    #[test]
+    fn test_evaluate_functions() {
+        let superset = superset(&[0x1000, 0x1001]);
+        let functions = evaluate_functions(&superset, &BTreeSet::from([0x1001, 0x1002]));
+        assert_eq!(functions.true_positives, BTreeSet::from([0x1001]));
+        assert_eq!(functions.false_positives, BTreeSet::from([0x1002]));
+        assert_eq!(functions.false_negatives, BTreeSet::from([0x1000]));
+        assert_eq!(functions.precision(), 0.5);
+        assert_eq!(functions.recall(), 0.5);
+
+        let result = DisassemblyResult {
+            predicted_instructions: BTreeSet::from([0x1000, 0x1001]),
+            predicted_functions: Some(BTreeSet::from([0x1000])),
+        };
+        let evaluation = evaluate_result(&superset, &result);
+        assert_eq!(evaluation.true_positives, BTreeSet::from([0x1000, 0x1001]));
+        assert_eq!(evaluation.false_negatives, BTreeSet::from([0x1002, 0x1003]));
+        let summary = evaluation.summary();
+        assert_eq!(summary.function_precision, Some(1.0));
+        assert_eq!(summary.function_recall, Some(0.5));
+    }
+
+    #[test]
+    fn test_unknown_function_starts() {
+        // e.g. a sample converted from an old format
+        let superset = superset(&[]);
+        let result = DisassemblyResult {
+            predicted_instructions: BTreeSet::from([0x1000]),
+            predicted_functions: Some(BTreeSet::from([0x1000])),
+        };
+        let evaluation = evaluate_result(&superset, &result);
+        assert!(evaluation.functions.is_none());
+        assert_eq!(evaluation.summary().function_recall, None);
+    }
+}
@@ -1,11 +1,10 @@
-use crate::loader::{dump_elf_symbols, load_executable};
+use crate::loader::{load_executable, load_sections, AnyElf};
 use crate::model::interval_set::Interval;
-use crate::model::{AddressClasses, ExecutableSample};
+use crate::model::{AddressClasses, ExecutableSample, SampleSource, SourceKind};
 use anyhow::{anyhow, Result};
 use anyhow::{bail, Context};
 use async_stream::try_stream;
 use futures_util::Stream;
-use object::read::elf::ElfFile32;
 use object::read::pe::PeFile32;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
@@ -72,6 +71,7 @@ fn read_pe_x86(platform_path: &Path, executable_name: &str) -> Result<Executable
         classes
             .true_instructions
             .push(Interval::from_start_and_end(start, end));
+        classes.function_starts.insert(start);
     }
     for thunk in thunks {
         // with thunks we only get the start address, so assume it's one instruction long and disassemble it
@@ -85,9 +85,16 @@ fn read_pe_x86(platform_path: &Path, executable_name: &str) -> Result<Executable
         classes
             .true_instructions
             .push(Interval::from_start_and_len(thunk, instr.len() as u32));
+        classes.function_starts.insert(thunk);
     }
 
-    ExecutableSample::new(memory, classes).context("Creating sample")
+    Ok(ExecutableSample::new(memory, classes)
+        .context("Creating sample")?
+        .with_source(SampleSource {
+            original_path: Some(format!("pe-x86/binary/{}", executable_name)),
+            ..SampleSource::new(SourceKind::Byteweight)
+        })
+        .with_sections(load_sections(&executable)?))
 }
 
 fn read_elf_x86(platform_path: &Path, executable_name: &str) -> Result<ExecutableSample> {
@@ -95,13 +102,19 @@ fn read_elf_x86(platform_path: &Path, executable_name: &str) -> Result<Executabl
 
     let executable = std::fs::read(&executable_path)
         .with_context(|| format!("Reading executable from {:?}", executable_path))?;
-    let executable = ElfFile32::parse(executable.as_slice())
+    let executable = AnyElf::parse(executable.as_slice())
         .with_context(|| format!("Parsing ELF file from {:?}", executable_path))?;
 
-    let memory = load_executable(&executable)?;
-    let classes = dump_elf_symbols(&memory, &executable)?;
+    let producer = executable.producer(None).context("Finding the producer")?;
 
-    ExecutableSample::new(memory, classes).context("Creating sample")
+    Ok(executable
+        .to_sample(None)
+        .context("Creating sample")?
+        .with_source(SampleSource {
+            original_path: Some(format!("elf-x86/binary/{}", executable_name)),
+            producer,
+            ..SampleSource::new(SourceKind::Byteweight)
+        }))
 }
 
 pub fn fetch_byteweight(
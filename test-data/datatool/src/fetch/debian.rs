@@ -1,4 +1,5 @@
-use crate::model::ExecutableSample;
+use crate::loader::AnyElf;
+use crate::model::{ExecutableSample, SampleSource, SourceKind};
 use crate::Interval;
 use anyhow::{anyhow, bail, Context, Result};
 use async_stream::try_stream;
@@ -6,8 +7,7 @@ use async_tar::{Archive, Entry, EntryType};
 use debian_packaging::deb::reader::{BinaryPackageEntry, BinaryPackageReader};
 use debian_packaging::repository::{BinaryPackageFetch, ReleaseReader};
 use futures_util::{pin_mut, AsyncRead, AsyncReadExt, Stream, StreamExt};
-use object::read::elf::ElfFile32;
-use object::{Architecture, Object};
+use object::Architecture;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
@@ -200,10 +200,10 @@ async fn extract_files<
 
 // #[derive(Yokeable)]
 #[repr(transparent)]
-struct YokableElf<'a>(ElfFile32<'a>);
+struct YokableElf<'a>(AnyElf<'a>);
 
 unsafe impl<'a> Yokeable<'a> for YokableElf<'static> {
-    type Output = ElfFile32<'a>;
+    type Output = AnyElf<'a>;
 
     fn transform(&'a self) -> &'a Self::Output {
         &self.0
@@ -248,9 +248,11 @@ async fn map_filter_exec(
 
         let buffer: Arc<[u8]> = Arc::from(buffer.as_ref());
 
-        if let Ok(elf) = YokeElf::try_attach_to_cart(buffer, |cart| ElfFile32::parse(cart)) {
-            if elf.get().architecture() == Architecture::I386
-                && elf.get().build_id().unwrap().is_some()
+        if let Ok(elf) = YokeElf::try_attach_to_cart(buffer, |cart| AnyElf::parse(cart)) {
+            if matches!(
+                elf.get().architecture(),
+                Architecture::I386 | Architecture::X86_64
+            ) && elf.get().build_id().unwrap().is_some()
             {
                 Some(elf)
             } else {
@@ -286,8 +288,11 @@ async fn map_filter_debug(
 
             let buffer: Arc<[u8]> = Arc::from(buffer.as_ref());
 
-            if let Ok(elf) = YokeElf::try_attach_to_cart(buffer, |cart| ElfFile32::parse(cart)) {
-                if elf.get().architecture() == Architecture::I386 {
+            if let Ok(elf) = YokeElf::try_attach_to_cart(buffer, |cart| AnyElf::parse(cart)) {
+                if matches!(
+                    elf.get().architecture(),
+                    Architecture::I386 | Architecture::X86_64
+                ) {
                     Some((buildid, elf))
                 } else {
                     None
@@ -306,6 +311,7 @@ type BPR = BinaryPackageReader<std::io::Cursor<Vec<u8>>>;
 
 fn process_package<'a>(
     package_name: &'a str,
+    package_version: Option<&'a str>,
     package: &'a mut BPR,
     debug_package: Option<&'a mut BPR>,
 ) -> impl Stream<Item = Result<(String, ExecutableSample)>> + 'a {
@@ -328,30 +334,45 @@ fn process_package<'a>(
             let debug_info = debugs.get(&build_id);
             info!("EXE {} {}", build_id, filename);
 
-            let sample = ExecutableSample::from_elf(
-                executable.get(),
-                debug_info.map(|v| v.get()),
-            )
+            let producer = executable
+                .get()
+                .producer(debug_info.map(|v| v.get()))
+                .with_context(|| {
+                    format!(
+                        "Finding the producer of executable {} in package {}",
+                        filename, package_name
+                    )
+                })?;
+
+            let sample = executable
+                .get()
+                .to_sample(debug_info.map(|v| v.get()))
             .with_context(|| {
                 format!(
                     "Parsing executable {} in package {}",
                     filename, package_name
                 )
-            })?;
+            })?
+            .with_source(SampleSource {
+                package_name: Some(package_name.to_string()),
+                package_version: package_version.map(|v| v.to_string()),
+                original_path: Some(filename.clone()),
+                producer,
+                ..SampleSource::new(SourceKind::Debian)
+            });
 
             // executable.
 
             // compute .text section coverage to filter out executables that have incomplete debug info
             // for gcc-compiled linux binaries we expect > 95% coverage
             let (covered, total) = {
-                use object::ObjectSection;
-                let text_region = executable
+                let (address, size) = executable
                     .get()
-                    .section_by_name(".text")
+                    .section_range(".text")
                     .ok_or_else(|| anyhow!("No .text section"))?;
 
-                let address = text_region.address().try_into().unwrap();
-                let size = text_region.size().try_into().unwrap();
+                let address = address.try_into().unwrap();
+                let size = size.try_into().unwrap();
 
                 let mut classes = sample.classes.clone();
                 classes.filter_to(Interval::from_start_and_len(address, size));
@@ -441,6 +462,7 @@ pub fn fetch_debian(
         for package_name in packages_to_fetch.keys() {
             let package = packages_to_fetch.get(package_name).unwrap();
             let debug_package = debug_packages_to_fetch.get(package_name);
+            let package_version = package.control_file.version_str().ok().map(|v| v.to_string());
 
             let mut package = repo_reader
                 .fetch_binary_package_deb_reader(package.clone())
@@ -464,7 +486,12 @@ pub fn fetch_debian(
                 None => None,
             };
 
-            let sample_stream = process_package(package_name, &mut package, debug_package.as_mut());
+            let sample_stream = process_package(
+                package_name,
+                package_version.as_deref(),
+                &mut package,
+                debug_package.as_mut(),
+            );
             pin_mut!(sample_stream);
             while let Some(r) = sample_stream.next().await {
                 let sample = r?;
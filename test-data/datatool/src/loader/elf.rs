@@ -0,0 +1,69 @@
+use crate::loader::find_elf_producer;
+use crate::model::ExecutableSample;
+use anyhow::{bail, Result};
+use object::read::elf::{ElfFile32, ElfFile64};
+use object::{FileKind, Object, ObjectSection};
+
+/// An ELF of either class, the i386 executables are 32-bit and the amd64 ones are 64-bit
+pub enum AnyElf<'data> {
+    Elf32(ElfFile32<'data>),
+    Elf64(ElfFile64<'data>),
+}
+
+impl<'data> AnyElf<'data> {
+    pub fn parse(data: &'data [u8]) -> Result<Self> {
+        Ok(match FileKind::parse(data)? {
+            FileKind::Elf32 => Self::Elf32(ElfFile32::parse(data)?),
+            FileKind::Elf64 => Self::Elf64(ElfFile64::parse(data)?),
+            kind => bail!("Not an ELF file: {:?}", kind),
+        })
+    }
+
+    pub fn architecture(&self) -> object::Architecture {
+        match self {
+            Self::Elf32(elf) => elf.architecture(),
+            Self::Elf64(elf) => elf.architecture(),
+        }
+    }
+
+    pub fn build_id(&self) -> Result<Option<&'data [u8]>> {
+        Ok(match self {
+            Self::Elf32(elf) => elf.build_id()?,
+            Self::Elf64(elf) => elf.build_id()?,
+        })
+    }
+
+    /// The address and the size of a section
+    pub fn section_range(&self, name: &str) -> Option<(u64, u64)> {
+        match self {
+            Self::Elf32(elf) => elf.section_by_name(name).map(|s| (s.address(), s.size())),
+            Self::Elf64(elf) => elf.section_by_name(name).map(|s| (s.address(), s.size())),
+        }
+    }
+
+    /// See [`find_elf_producer`]
+    pub fn producer(&self, debug_info: Option<&Self>) -> Result<Option<String>> {
+        match (self, debug_info) {
+            (Self::Elf32(elf), None) => find_elf_producer(elf, None),
+            (Self::Elf64(elf), None) => find_elf_producer(elf, None),
+            (Self::Elf32(elf), Some(Self::Elf32(debug))) => find_elf_producer(elf, Some(debug)),
+            (Self::Elf64(elf), Some(Self::Elf64(debug))) => find_elf_producer(elf, Some(debug)),
+            _ => bail!("The debug info and the executable are of different ELF classes"),
+        }
+    }
+
+    /// See [`ExecutableSample::from_elf`]
+    pub fn to_sample(&self, debug_info: Option<&Self>) -> Result<ExecutableSample> {
+        match (self, debug_info) {
+            (Self::Elf32(elf), None) => ExecutableSample::from_elf(elf, None),
+            (Self::Elf64(elf), None) => ExecutableSample::from_elf(elf, None),
+            (Self::Elf32(elf), Some(Self::Elf32(debug))) => {
+                ExecutableSample::from_elf(elf, Some(debug))
+            }
+            (Self::Elf64(elf), Some(Self::Elf64(debug))) => {
+                ExecutableSample::from_elf(elf, Some(debug))
+            }
+            _ => bail!("The debug info and the executable are of different ELF classes"),
+        }
+    }
+}
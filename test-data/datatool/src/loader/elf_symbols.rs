@@ -2,8 +2,8 @@ use crate::model::AddressClasses;
 use crate::Interval;
 use anyhow::Result;
 use memory_image::MemoryImage;
-use object::read::elf::{ElfFile32, FileHeader};
-use object::{elf, Object};
+use object::elf;
+use object::read::elf::{ElfFile, FileHeader};
 use once_cell::sync::Lazy;
 use regex::bytes::{Regex, RegexBuilder};
 use std::collections::HashMap;
@@ -316,9 +316,12 @@ static KNOWN_FUNCTIONS: Lazy<HashMap<&str, Regex>> = Lazy::new(|| {
     )
 });
 
-pub fn dump_elf_symbols(memory: &MemoryImage, elf: &ElfFile32) -> Result<AddressClasses> {
+pub fn dump_elf_symbols<Elf: FileHeader>(
+    memory: &MemoryImage,
+    elf: &ElfFile<Elf>,
+) -> Result<AddressClasses> {
     use object::read::elf::Sym;
-    let e = elf.endianness();
+    let e = elf.endian();
 
     let sections = elf.raw_header().sections(e, elf.data())?;
     let symbol_table = sections.symbols(e, elf.data(), elf::SHT_SYMTAB)?;
@@ -329,13 +332,19 @@ pub fn dump_elf_symbols(memory: &MemoryImage, elf: &ElfFile32) -> Result<Address
 
     for symbol in symbol_table.iter() {
         // skip undefined symbols
-        if symbol.st_shndx.get(e) == elf::SHN_UNDEF {
+        if symbol.st_shndx(e) == elf::SHN_UNDEF {
             continue;
         }
 
-        let address = symbol.st_value.get(e);
-        let mut size = symbol.st_size.get(e);
         let name = std::str::from_utf8(symbol.name(e, symbol_table.strings())?)?;
+        // the samples use 32-bit addresses, 64-bit executables have to be mapped below 4 GiB
+        let (Ok(address), Ok(mut size)) = (
+            u32::try_from(symbol.st_value(e).into()),
+            u32::try_from(symbol.st_size(e).into()),
+        ) else {
+            warn!("symbol {} does not fit into 32-bit address space", name);
+            continue;
+        };
         let kind = symbol.st_type();
 
         // skip uninteresting symbols
@@ -366,13 +375,25 @@ pub fn dump_elf_symbols(memory: &MemoryImage, elf: &ElfFile32) -> Result<Address
             }
         };
 
+        if size != 0 && matches!(kind, elf::STT_OBJECT | elf::STT_COMMON | elf::STT_FUNC) {
+            classes.symbols.insert(
+                Interval::from_start_and_len(address, size),
+                name.to_string(),
+            );
+        }
+
         match kind {
             elf::STT_OBJECT | elf::STT_COMMON => classes
                 .true_data
                 .push(Interval::from_start_and_len(address, size)),
-            elf::STT_FUNC => classes
-                .true_instructions
-                .push(Interval::from_start_and_len(address, size)),
+            elf::STT_FUNC => {
+                if size != 0 {
+                    classes.function_starts.insert(address);
+                }
+                classes
+                    .true_instructions
+                    .push(Interval::from_start_and_len(address, size))
+            }
             elf::STT_NOTYPE => {}
             kind => panic!("Unknown symbol type: {}", kind),
         };
@@ -1,14 +1,20 @@
+mod elf;
 mod elf_symbols;
 mod pdb;
+mod producer;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use memory_image::{MemoryImage, Protection};
 use object::elf::{PF_R, PF_W, PF_X};
 use object::pe::{IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_WRITE};
-use object::{Object, ObjectSegment, SegmentFlags};
+use object::{Object, ObjectSection, ObjectSegment, SectionKind, SegmentFlags};
+
+use crate::model::interval_set::{Interval, IntervalMap};
 
 pub use self::pdb::dump_pdb;
+pub use elf::AnyElf;
 pub use elf_symbols::dump_elf_symbols;
+pub use producer::find_elf_producer;
 
 fn flags_to_protection(flags: SegmentFlags) -> Protection {
     match flags {
@@ -76,7 +82,8 @@ pub fn load_executable<'data: 'file, 'file>(
     // let is_dyn = elf.raw_header().e_type.get(elf.endian()) == ET_DYN;
 
     for segment in object.segments() {
-        let addr = segment.address() as u32;
+        let addr = u32::try_from(segment.address())
+            .context("Segment is not in the 32-bit address space")?;
         let mut data = segment.data().unwrap().to_vec();
 
         while (data.len() as u64) < segment.size() {
@@ -90,3 +97,31 @@ pub fn load_executable<'data: 'file, 'file>(
 
     Ok(res)
 }
+
+/// Maps the address ranges of the loaded sections to their names
+pub fn load_sections<'data: 'file, 'file>(
+    object: &'file impl Object<'data, 'file>,
+) -> Result<IntervalMap<u32, String>> {
+    let mut res = IntervalMap::new();
+
+    for section in object.sections() {
+        // the sections that are not loaded (e.g. debug info) have zero address
+        if section.address() == 0 || section.size() == 0 {
+            continue;
+        }
+        // .tbss has an address, but takes no space and overlaps the next section
+        if section.kind() == SectionKind::UninitializedTls {
+            continue;
+        }
+
+        let addr = u32::try_from(section.address())
+            .context("Section is not in the 32-bit address space")?;
+        let size = u32::try_from(section.size()).context("Section is too big")?;
+        res.insert(
+            Interval::from_start_and_len(addr, size),
+            section.name()?.to_string(),
+        );
+    }
+
+    Ok(res)
+}
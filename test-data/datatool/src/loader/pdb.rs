@@ -2,11 +2,13 @@ use crate::model::interval_set::Interval;
 use crate::model::AddressClasses;
 use anyhow::Result;
 use pdb::{AddressMap, FallibleIterator, PDB};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 struct Ranges {
-    instruction_ranges: Vec<(u32, u32)>,
-    data_locations: BTreeSet<u32>,
+    /// (address, length, name), the trampolines don't have names
+    instruction_ranges: Vec<(u32, u32, Option<String>)>,
+    /// address -> name
+    data_locations: BTreeMap<u32, String>,
 }
 
 fn extract_symbol(
@@ -17,17 +19,25 @@ fn extract_symbol(
     match symbol.parse()? {
         pdb::SymbolData::Data(data) => {
             if let Some(addr) = data.offset.to_rva(address_map) {
-                ranges.data_locations.insert(addr.0);
+                ranges
+                    .data_locations
+                    .insert(addr.0, data.name.to_string().into_owned());
             }
         }
         pdb::SymbolData::Procedure(data) => {
             if let Some(addr) = data.offset.to_rva(address_map) {
-                ranges.instruction_ranges.push((addr.0, data.len as u32));
+                ranges.instruction_ranges.push((
+                    addr.0,
+                    data.len,
+                    Some(data.name.to_string().into_owned()),
+                ));
             }
         }
         pdb::SymbolData::Trampoline(data) => {
             if let Some(addr) = data.thunk.to_rva(address_map) {
-                ranges.instruction_ranges.push((addr.0, data.size as u32));
+                ranges
+                    .instruction_ranges
+                    .push((addr.0, data.size as u32, None));
             }
         }
         _ => {
@@ -56,7 +66,7 @@ pub fn dump_pdb<'a, T: pdb::Source<'a> + 'a>(
 ) -> Result<AddressClasses> {
     let mut ranges = Ranges {
         instruction_ranges: Vec::new(),
-        data_locations: BTreeSet::new(),
+        data_locations: BTreeMap::new(),
     };
 
     let address_map = pdb.address_map()?;
@@ -75,13 +85,14 @@ pub fn dump_pdb<'a, T: pdb::Source<'a> + 'a>(
     let instruction_starts = ranges
         .instruction_ranges
         .iter()
-        .map(|&(a, _)| a)
+        .map(|&(a, _, _)| a)
         .collect::<BTreeSet<_>>();
 
     let mut classes = AddressClasses::new();
+    let mut data_names = Vec::new();
 
     let mut instr_iter = instruction_starts.iter().peekable();
-    for &addr in ranges.data_locations.iter() {
+    for (&addr, name) in ranges.data_locations.iter() {
         while let Some(&&next_instr) = instr_iter.peek() {
             if next_instr >= addr {
                 break;
@@ -95,14 +106,22 @@ pub fn dump_pdb<'a, T: pdb::Source<'a> + 'a>(
             classes
                 .true_data
                 .push(Interval::from_start_and_len(addr, len));
+            data_names.push((Interval::from_start_and_len(addr, len), name));
         }
     }
 
-    for (addr, len) in ranges.instruction_ranges {
-        classes
-            .true_instructions
-            .push(Interval::from_start_and_len(addr, len));
+    for (addr, len, name) in ranges.instruction_ranges {
+        let interval = Interval::from_start_and_len(addr, len);
+        classes.true_instructions.push(interval);
+        if let Some(name) = name {
+            classes.symbols.insert(interval, name);
+        }
+    }
+    // the data (e.g. jump tables) can be inside of the procedures, so it goes on top of them
+    for (interval, name) in data_names {
+        classes.symbols.insert(interval, name.clone());
     }
+    classes.function_starts = instruction_starts;
 
     for data_interval in classes.true_data.iter() {
         // MSVC PDB reports jump tables as being inside the function
@@ -0,0 +1,77 @@
+use anyhow::Result;
+use object::read::elf::{ElfFile, FileHeader};
+use object::{Object, ObjectSection};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// Find the most common DW_AT_producer among the compilation units
+fn find_dwarf_producer<Elf: FileHeader>(elf: &ElfFile<Elf>) -> Result<Option<String>> {
+    let load_section = |id: gimli::SectionId| -> Result<Cow<[u8]>, gimli::Error> {
+        Ok(elf
+            .section_by_name(id.name())
+            .and_then(|section| section.uncompressed_data().ok())
+            .unwrap_or(Cow::Borrowed(&[])))
+    };
+
+    let dwarf = gimli::Dwarf::load(&load_section)?;
+    let dwarf = dwarf.borrow(|section| gimli::EndianSlice::new(section, gimli::LittleEndian));
+
+    let mut producers = BTreeMap::<String, usize>::new();
+
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut entries = unit.entries();
+        if let Some((_, entry)) = entries.next_dfs()? {
+            if let Some(attr) = entry.attr_value(gimli::DW_AT_producer)? {
+                let producer = dwarf.attr_string(&unit, attr)?;
+                *producers
+                    .entry(producer.to_string_lossy().into_owned())
+                    .or_insert(0) += 1;
+            }
+        }
+    }
+
+    // the assembler-produced units (crt files & co) are not interesting
+    Ok(producers
+        .into_iter()
+        .filter(|(producer, _)| !producer.starts_with("GNU AS"))
+        .max_by_key(|&(_, count)| count)
+        .map(|(producer, _)| producer))
+}
+
+/// Collect the unique strings from the .comment section
+fn find_comment_producer<Elf: FileHeader>(elf: &ElfFile<Elf>) -> Result<Option<String>> {
+    let Some(section) = elf.section_by_name(".comment") else {
+        return Ok(None);
+    };
+    let data = section.uncompressed_data()?;
+
+    let mut comments = Vec::new();
+    for comment in data.split(|&b| b == 0) {
+        let comment = String::from_utf8_lossy(comment).trim().to_string();
+        if !comment.is_empty() && !comments.contains(&comment) {
+            comments.push(comment);
+        }
+    }
+
+    Ok((!comments.is_empty()).then(|| comments.join("; ")))
+}
+
+/// Find out which compiler produced the executable
+///
+/// Prefers the DW_AT_producer of the debug info (it includes the compiler flags), falling back to the .comment section
+pub fn find_elf_producer<Elf: FileHeader>(
+    executable: &ElfFile<Elf>,
+    debug_info: Option<&ElfFile<Elf>>,
+) -> Result<Option<String>> {
+    if let Some(producer) = find_dwarf_producer(debug_info.unwrap_or(executable))? {
+        return Ok(Some(producer));
+    }
+    if let Some(debug_info) = debug_info {
+        if let Some(producer) = find_comment_producer(debug_info)? {
+            return Ok(Some(producer));
+        }
+    }
+    find_comment_producer(executable)
+}
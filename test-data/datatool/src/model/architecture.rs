@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// The instruction set the code in the sample is meant to be decoded with
+///
+/// The addresses are still 32-bit, so the 64-bit images have to be mapped below 4 GiB
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Architecture {
+    X86_16,
+    #[default]
+    X86_32,
+    X86_64,
+}
+
+impl Architecture {
+    /// Bitness as expected by [`iced_x86::Decoder::new`]
+    pub fn bitness(self) -> u32 {
+        match self {
+            Architecture::X86_16 => 16,
+            Architecture::X86_32 => 32,
+            Architecture::X86_64 => 64,
+        }
+    }
+}
+
+impl Display for Architecture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Architecture::X86_16 => write!(f, "x86-16"),
+            Architecture::X86_32 => write!(f, "x86-32"),
+            Architecture::X86_64 => write!(f, "x86-64"),
+        }
+    }
+}
@@ -0,0 +1,395 @@
+//! An archive holding many `.sample` files
+//!
+//! The layout is:
+//! - the header: [`ARCHIVE_MAGIC`] and the archive format version
+//! - the entries, each one is `[name length: u32][name][data length: u64][data]`, where `data` is a `.sample` file as-is
+//! - the index: `[entry count: u64]` followed by `[name length: u32][name][data offset: u64][data length: u64]` for each entry
+//! - the footer: `[index offset: u64]` and [`INDEX_MAGIC`]
+//!
+//! Appending to an archive truncates the index, writes the new entries and writes the new index in the end.
+//! If the writer did not get to write the index (e.g. it crashed), the index is rebuilt by scanning the entries.
+//!
+//! All integers are little-endian.
+
+use crate::model::ExecutableSample;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const ARCHIVE_MAGIC: [u8; 8] = *b"IX86ARCH";
+pub const INDEX_MAGIC: [u8; 8] = *b"IX86AIDX";
+pub const ARCHIVE_VERSION: u32 = 1;
+
+const HEADER_SIZE: u64 = ARCHIVE_MAGIC.len() as u64 + 4;
+const FOOTER_SIZE: u64 = 8 + INDEX_MAGIC.len() as u64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct IndexEntry {
+    offset: u64,
+    len: u64,
+}
+
+fn read_u32(input: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(input: &mut impl Read) -> std::io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Reads an entry name, `limit` is how many bytes are left for it in the file
+///
+/// The length is checked before allocating, so a corrupted one is an error rather than a huge allocation
+fn read_name(input: &mut impl Read, limit: u64) -> Result<String> {
+    let len = read_u32(input)?;
+    if len as u64 > limit {
+        bail!("Entry name length {} runs past the end of the archive", len);
+    }
+    let mut name = vec![0; len as usize];
+    input.read_exact(&mut name)?;
+    String::from_utf8(name).context("Entry name is not a valid UTF-8")
+}
+
+fn write_name(output: &mut impl Write, name: &str) -> std::io::Result<()> {
+    output.write_all(&(name.len() as u32).to_le_bytes())?;
+    output.write_all(name.as_bytes())
+}
+
+fn read_header(file: &mut File) -> Result<()> {
+    let mut magic = [0; ARCHIVE_MAGIC.len()];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut magic)
+        .context("Reading the archive header")?;
+    if magic != ARCHIVE_MAGIC {
+        bail!("Not a sample archive (bad magic)");
+    }
+    let version = read_u32(file)?;
+    if version != ARCHIVE_VERSION {
+        bail!(
+            "Unsupported archive version {} (expected {})",
+            version,
+            ARCHIVE_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Reads the index, returning it along with the offset where the entries end
+fn read_index(file: &mut File) -> Result<(BTreeMap<String, IndexEntry>, u64)> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+
+    if file_len >= HEADER_SIZE + FOOTER_SIZE {
+        file.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        let index_offset = read_u64(file)?;
+        let mut magic = [0; INDEX_MAGIC.len()];
+        file.read_exact(&mut magic)?;
+
+        if magic == INDEX_MAGIC && (HEADER_SIZE..=file_len - FOOTER_SIZE).contains(&index_offset) {
+            file.seek(SeekFrom::Start(index_offset))?;
+            let mut input = BufReader::new(&mut *file);
+            let count = read_u64(&mut input)?;
+
+            let mut index = BTreeMap::new();
+            for _ in 0..count {
+                let name = read_name(&mut input, file_len - FOOTER_SIZE - index_offset)?;
+                let offset = read_u64(&mut input)?;
+                let len = read_u64(&mut input)?;
+                // the data is read into memory as a whole, so it has to be checked too
+                let end = offset.checked_add(len).filter(|&end| end <= index_offset);
+                if offset < HEADER_SIZE || end.is_none() {
+                    bail!("Corrupted archive index: entry {} is out of bounds", name);
+                }
+                index.insert(name, IndexEntry { offset, len });
+            }
+
+            return Ok((index, index_offset));
+        }
+    }
+
+    scan_entries(file, file_len)
+}
+
+/// Rebuilds the index by walking over the entries
+///
+/// A truncated trailing entry (left by an interrupted writer) is ignored
+fn scan_entries(file: &mut File, file_len: u64) -> Result<(BTreeMap<String, IndexEntry>, u64)> {
+    let mut index = BTreeMap::new();
+    let mut position = HEADER_SIZE;
+
+    file.seek(SeekFrom::Start(position))?;
+    let mut input = BufReader::new(&mut *file);
+    loop {
+        let Ok(name) = read_name(&mut input, file_len.saturating_sub(position + 4)) else {
+            break;
+        };
+        let Ok(len) = read_u64(&mut input) else {
+            break;
+        };
+        let offset = position + 4 + name.len() as u64 + 8;
+        let Some(end) = offset.checked_add(len).filter(|&end| end <= file_len) else {
+            break;
+        };
+        input.seek_relative(len as i64)?;
+
+        index.insert(name, IndexEntry { offset, len });
+        position = end;
+    }
+
+    Ok((index, position))
+}
+
+/// Read-only access to a sample archive
+///
+/// Each read opens the file anew, so the samples can be read from multiple threads
+pub struct SampleArchive {
+    path: PathBuf,
+    index: BTreeMap<String, IndexEntry>,
+}
+
+impl SampleArchive {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        read_header(&mut file)?;
+        let (index, _) = read_index(&mut file)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            index,
+        })
+    }
+
+    /// Names of the samples in the archive, in sorted order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.index.keys().map(|v| v.as_str())
+    }
+
+    /// Reads the `.sample` file stored under `name` without decoding it
+    pub fn read_raw(&self, name: &str) -> Result<Vec<u8>> {
+        let Some(entry) = self.index.get(name) else {
+            bail!("Sample {} not found in {}", name, self.path.display());
+        };
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        let mut data = vec![0; entry.len as usize];
+        file.read_exact(&mut data)?;
+
+        Ok(data)
+    }
+
+    pub fn read(&self, name: &str) -> Result<ExecutableSample> {
+        ExecutableSample::deserialize_from(&mut self.read_raw(name)?.as_slice())
+    }
+}
+
+/// Writes samples to an archive one by one
+///
+/// The index is written by [`SampleArchiveWriter::finish`]
+pub struct SampleArchiveWriter {
+    output: BufWriter<File>,
+    position: u64,
+    index: BTreeMap<String, IndexEntry>,
+}
+
+impl SampleArchiveWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut output = BufWriter::new(File::create(path)?);
+        output.write_all(&ARCHIVE_MAGIC)?;
+        output.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+
+        Ok(Self {
+            output,
+            position: HEADER_SIZE,
+            index: BTreeMap::new(),
+        })
+    }
+
+    /// Opens an existing archive to add more samples to it (or creates a new one)
+    pub fn append(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Self::create(path);
+        }
+
+        let mut file = File::options().read(true).write(true).open(path)?;
+        read_header(&mut file)?;
+        let (index, entries_end) = read_index(&mut file)?;
+
+        // drop the old index, a new one will be written on finish
+        file.set_len(entries_end)?;
+        file.seek(SeekFrom::Start(entries_end))?;
+
+        Ok(Self {
+            output: BufWriter::new(file),
+            position: entries_end,
+            index,
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    /// Adds an already encoded `.sample` file
+    pub fn add_raw(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        if self.index.contains_key(name) {
+            bail!("Sample {} is already in the archive", name);
+        }
+
+        write_name(&mut self.output, name)?;
+        self.output.write_all(&(data.len() as u64).to_le_bytes())?;
+        self.output.write_all(data)?;
+
+        let offset = self.position + 4 + name.len() as u64 + 8;
+        let len = data.len() as u64;
+        self.index
+            .insert(name.to_string(), IndexEntry { offset, len });
+        self.position = offset + len;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        let index_offset = self.position;
+
+        self.output
+            .write_all(&(self.index.len() as u64).to_le_bytes())?;
+        for (name, entry) in self.index.iter() {
+            write_name(&mut self.output, name)?;
+            self.output.write_all(&entry.offset.to_le_bytes())?;
+            self.output.write_all(&entry.len.to_le_bytes())?;
+        }
+
+        self.output.write_all(&index_offset.to_le_bytes())?;
+        self.output.write_all(&INDEX_MAGIC)?;
+        self.output.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::interval_set::Interval;
+    use crate::model::AddressClasses;
+    use memory_image::{MemoryImage, Protection};
+
+    fn sample(fill: u8) -> ExecutableSample {
+        let mut classes = AddressClasses::new();
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0, 10));
+
+        let mut memory = MemoryImage::new();
+        memory.add_region(0, Protection::READ_EXECUTE, vec![fill; 20], "".to_string());
+
+        ExecutableSample::new(memory, classes).unwrap()
+    }
+
+    fn add(writer: &mut SampleArchiveWriter, name: &str, sample: &ExecutableSample) -> Result<()> {
+        let mut data = Vec::new();
+        sample.serialize_into(&mut data)?;
+        writer.add_raw(name, &data)
+    }
+
+    fn first_byte(sample: &ExecutableSample) -> u8 {
+        sample.memory.execute_all_at(0)[0]
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("samples.archive");
+
+        let mut writer = SampleArchiveWriter::create(&path).unwrap();
+        add(&mut writer, "b/second", &sample(2)).unwrap();
+        add(&mut writer, "a/first", &sample(1)).unwrap();
+        assert!(add(&mut writer, "a/first", &sample(1)).is_err());
+        writer.finish().unwrap();
+
+        let mut writer = SampleArchiveWriter::append(&path).unwrap();
+        add(&mut writer, "c/third", &sample(3)).unwrap();
+        writer.finish().unwrap();
+
+        let archive = SampleArchive::open(&path).unwrap();
+        assert_eq!(
+            archive.names().collect::<Vec<_>>(),
+            vec!["a/first", "b/second", "c/third"]
+        );
+        assert_eq!(first_byte(&archive.read("a/first").unwrap()), 1);
+        assert_eq!(first_byte(&archive.read("b/second").unwrap()), 2);
+        assert_eq!(first_byte(&archive.read("c/third").unwrap()), 3);
+        assert!(archive.read("d/missing").is_err());
+    }
+
+    #[test]
+    fn test_rebuild_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("samples.archive");
+
+        let mut writer = SampleArchiveWriter::create(&path).unwrap();
+        add(&mut writer, "first", &sample(1)).unwrap();
+        add(&mut writer, "second", &sample(2)).unwrap();
+        // simulate a writer that was killed in the middle of the second entry
+        drop(writer);
+        let len = std::fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 5)
+            .unwrap();
+
+        let archive = SampleArchive::open(&path).unwrap();
+        assert_eq!(archive.names().collect::<Vec<_>>(), vec!["first"]);
+
+        let mut writer = SampleArchiveWriter::append(&path).unwrap();
+        add(&mut writer, "second", &sample(2)).unwrap();
+        writer.finish().unwrap();
+
+        let archive = SampleArchive::open(&path).unwrap();
+        assert_eq!(archive.names().collect::<Vec<_>>(), vec!["first", "second"]);
+        assert_eq!(first_byte(&archive.read("second").unwrap()), 2);
+    }
+
+    #[test]
+    fn test_corrupted_lengths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("samples.archive");
+
+        let mut writer = SampleArchiveWriter::create(&path).unwrap();
+        add(&mut writer, "first", &sample(1)).unwrap();
+        writer.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let index_offset =
+            u64::from_le_bytes(data[data.len() - 16..data.len() - 8].try_into().unwrap()) as usize;
+        // the index is `[count][name length][name][offset][length]`
+        let name_len = index_offset + 8;
+        let data_len = name_len + 4 + "first".len() + 8;
+
+        let mut corrupted = data.clone();
+        corrupted[name_len..name_len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &corrupted).unwrap();
+        assert!(SampleArchive::open(&path).is_err());
+
+        let mut corrupted = data.clone();
+        corrupted[data_len..data_len + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, &corrupted).unwrap();
+        assert!(SampleArchive::open(&path).is_err());
+
+        // without the index the entries are scanned, the garbage after the first one is ignored
+        let mut corrupted = data[..index_offset].to_vec();
+        corrupted.extend_from_slice(&u32::MAX.to_le_bytes());
+        corrupted.extend_from_slice(&[0; 16]);
+        std::fs::write(&path, &corrupted).unwrap();
+        let archive = SampleArchive::open(&path).unwrap();
+        assert_eq!(archive.names().collect::<Vec<_>>(), vec!["first"]);
+        assert_eq!(first_byte(&archive.read("first").unwrap()), 1);
+    }
+}
@@ -0,0 +1,88 @@
+use super::Index32;
+
+/// Larger gaps between the addresses start a new region
+const MAX_GAP: u64 = 0x1000;
+const NO_NODE: Index32 = Index32::MAX;
+
+struct IndexRegion {
+    start: u64,
+    /// Node index per address starting at `start`, [`NO_NODE`] for the gaps
+    indices: Vec<Index32>,
+}
+
+/// Maps the superset addresses to the node indices
+///
+/// The superset covers every byte of the selected regions, so a vector per region is both smaller and
+/// faster than a hash map
+pub struct AddressIndex {
+    /// Sorted by the start address
+    regions: Vec<IndexRegion>,
+}
+
+impl AddressIndex {
+    pub fn new(addresses: &[u32]) -> Self {
+        let mut sorted = addresses
+            .iter()
+            .enumerate()
+            .map(|(i, &address)| (address as u64, i as Index32))
+            .collect::<Vec<_>>();
+        // the superset is normally sorted already
+        if !sorted.windows(2).all(|w| w[0].0 <= w[1].0) {
+            sorted.sort_unstable();
+        }
+
+        let mut regions: Vec<IndexRegion> = Vec::new();
+        for (address, index) in sorted {
+            let region = match regions.last_mut() {
+                Some(region) if address < region.start + region.indices.len() as u64 + MAX_GAP => {
+                    region
+                }
+                _ => {
+                    regions.push(IndexRegion {
+                        start: address,
+                        indices: Vec::new(),
+                    });
+                    regions.last_mut().unwrap()
+                }
+            };
+            let offset = (address - region.start) as usize;
+            if offset >= region.indices.len() {
+                region.indices.resize(offset + 1, NO_NODE);
+            }
+            region.indices[offset] = index;
+        }
+
+        Self { regions }
+    }
+
+    /// Takes 64-bit addresses, as the jump targets can be anywhere in the 64-bit address space
+    pub fn get(&self, address: u64) -> Option<Index32> {
+        let region = self.regions.partition_point(|r| r.start <= address);
+        let region = &self.regions[region.checked_sub(1)?];
+        region
+            .indices
+            .get((address - region.start) as usize)
+            .copied()
+            .filter(|&index| index != NO_NODE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let addresses = [0x1000, 0x1001, 0x1003, 0x8000_0000, 0x8000_0001, 0x1002];
+        let index = AddressIndex::new(&addresses);
+        assert_eq!(index.regions.len(), 2);
+
+        for (i, &address) in addresses.iter().enumerate() {
+            assert_eq!(index.get(address as u64), Some(i as Index32));
+        }
+        for address in [0, 0xfff, 0x1004, 0x7fff_ffff, 0x8000_0002, 0x1_0000_1000] {
+            assert_eq!(index.get(address), None);
+        }
+        assert_eq!(AddressIndex::new(&[]).get(0), None);
+    }
+}
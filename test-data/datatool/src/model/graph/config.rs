@@ -0,0 +1,205 @@
+use crate::model::graph::RelationType;
+use anyhow::anyhow;
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use strum::IntoEnumIterator;
+
+/// The relation types put into the graph, the rest are not built at all
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct RelationSet(BTreeSet<RelationType>);
+
+impl RelationSet {
+    pub fn all() -> Self {
+        Self(RelationType::iter().collect())
+    }
+
+    /// `Next` to `DataDependent`, the relations the models were first trained on (see [`super::RELATION_VERSION`])
+    pub fn version_1() -> Self {
+        Self(
+            RelationType::iter()
+                .take_while(|&r| r <= RelationType::DataDependent)
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, relation: RelationType) -> bool {
+        self.0.contains(&relation)
+    }
+
+    pub fn contains_any(&self, relations: &[RelationType]) -> bool {
+        relations.iter().any(|&r| self.contains(r))
+    }
+
+    /// How many relation types a model needs for these, the numbering goes up to the last one
+    pub fn relation_count(&self) -> usize {
+        self.0.last().map_or(0, |&r| r as usize + 1)
+    }
+}
+
+impl Default for RelationSet {
+    fn default() -> Self {
+        Self::version_1()
+    }
+}
+
+impl FromStr for RelationSet {
+    type Err = anyhow::Error;
+
+    /// Parses `all` or a list like `next,previous,jump_to,jump_from`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(Self::all());
+        }
+        s.split(',')
+            .map(|name| {
+                RelationType::from_str(name.trim())
+                    .map_err(|_| anyhow!("Unknown relation type {}", name))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Self)
+    }
+}
+
+impl Display for RelationSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if *self == Self::all() {
+            return write!(f, "all");
+        }
+        let names = self.0.iter().map(|&r| <&str>::from(r)).collect::<Vec<_>>();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+bitflags! {
+    /// Optional node feature arrays written to the npz (if the superset has them)
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct NodeFeatures: u16 {
+        /// See [`crate::model::SupersetConfig::has_references`]
+        const REFERENCES = 1 << 0;
+        /// See [`crate::model::SupersetConfig::jump_tables`]
+        const DATA_HINTS = 1 << 1;
+        /// See [`crate::model::SupersetConfig::extra_features`]
+        const EXTRA = 1 << 2;
+        /// Whether the instruction falls through and whether it has a direct branch target
+        const CONTROL_FLOW = 1 << 3;
+        /// The registers used and defined by the instruction (of the tracked register classes)
+        const REGISTERS = 1 << 4;
+        /// Whether the bytes don't decode to a valid instruction
+        const INVALID = 1 << 5;
+        /// Whether the instruction can only run in the kernel mode
+        const PRIVILEGED = 1 << 6;
+        /// Protection of the memory region of the node
+        const PROTECTION = 1 << 7;
+        /// The byte at the node address
+        const BYTES = 1 << 8;
+    }
+}
+
+impl Default for NodeFeatures {
+    /// The features exported before the rest were added, so that the old configs make the same graphs
+    fn default() -> Self {
+        NodeFeatures::REFERENCES | NodeFeatures::DATA_HINTS | NodeFeatures::EXTRA
+    }
+}
+
+fn parse_node_features(input: &str) -> Result<NodeFeatures, bitflags::parser::ParseError> {
+    // bitflags 2.0 only implements `FromStr` for the inner type
+    Ok(NodeFeatures(input.parse()?))
+}
+
+/// How the graph is built from the superset, it's written to the npz metadata
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, clap::Args)]
+#[serde(default)]
+pub struct GraphConfig {
+    /// Relation types to build: `all` or a list like `next,previous,data_dependency,data_dependent`
+    #[clap(
+        long,
+        default_value = "next,previous,overlap,jump_to,jump_from,data_dependency,data_dependent"
+    )]
+    pub relations: RelationSet,
+    /// The most definitions of a register (or a stack slot) reaching a node, the closest ones are kept
+    #[clap(long)]
+    pub max_data_dependencies: Option<u32>,
+    /// The definitions are not propagated to the nodes further away than this many bytes
+    #[clap(long)]
+    pub max_data_dependency_distance: Option<u32>,
+    /// Optional node features to export, e.g. "REFERENCES | DATA_HINTS | EXTRA | CONTROL_FLOW | REGISTERS | INVALID | PRIVILEGED | PROTECTION | BYTES"
+    #[clap(long, value_parser = parse_node_features, default_value = "REFERENCES | DATA_HINTS | EXTRA")]
+    pub node_features: NodeFeatures,
+    /// Drop the edges from a node to itself (like the `JumpTo` of `jmp $`)
+    #[clap(long)]
+    pub drop_self_loops: bool,
+    /// Split the samples into graphs of this many bytes (see [`crate::model::GraphSample::new_windowed`])
+    #[clap(long)]
+    pub window_size: Option<u32>,
+    /// Bytes around each window that are added to its graph for the context
+    #[clap(long, default_value_t = 0x1000)]
+    pub window_halo: u32,
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        Self {
+            relations: RelationSet::version_1(),
+            max_data_dependencies: None,
+            max_data_dependency_distance: None,
+            node_features: NodeFeatures::default(),
+            drop_self_loops: false,
+            window_size: None,
+            window_halo: 0x1000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use strum::EnumCount;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(RelationSet::from_str("all").unwrap(), RelationSet::all());
+        let relations = RelationSet::from_str("next,previous, call_to").unwrap();
+        assert!(relations.contains(RelationType::CallTo));
+        assert!(!relations.contains(RelationType::Overlap));
+        assert_eq!(relations.to_string(), "next,previous,call_to");
+        assert!(RelationSet::from_str("next,sideways").is_err());
+
+        let config: GraphConfig = serde_yaml::from_str(
+            "relations: [next, previous]\nmax_data_dependencies: 4\nnode_features: EXTRA\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.relations,
+            RelationSet::from_str("next,previous").unwrap()
+        );
+        assert_eq!(config.max_data_dependencies, Some(4));
+        assert_eq!(config.node_features, NodeFeatures::EXTRA);
+
+        let config: GraphConfig =
+            serde_yaml::from_str("node_features: REGISTERS | PROTECTION | BYTES\n").unwrap();
+        assert!(config.node_features.contains(NodeFeatures::BYTES));
+        assert!(!config.node_features.contains(NodeFeatures::CONTROL_FLOW));
+        assert_eq!(
+            parse_node_features("CONTROL_FLOW | INVALID").unwrap(),
+            NodeFeatures::CONTROL_FLOW | NodeFeatures::INVALID
+        );
+
+        let config: GraphConfig = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(config, GraphConfig::default());
+        // the same relations as the CLI default
+        assert_eq!(
+            config.relations,
+            RelationSet::from_str(
+                "next,previous,overlap,jump_to,jump_from,data_dependency,data_dependent"
+            )
+            .unwrap()
+        );
+        assert_eq!(config.relations.relation_count(), 7);
+        assert_eq!(RelationSet::all().relation_count(), RelationType::COUNT);
+    }
+}
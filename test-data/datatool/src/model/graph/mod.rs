@@ -0,0 +1,1675 @@
+mod address_index;
+mod config;
+#[allow(unused)]
+mod dummy_single_vec;
+mod single_vec;
+mod window;
+
+pub use address_index::AddressIndex;
+pub use config::{GraphConfig, NodeFeatures, RelationSet};
+pub use window::GraphWindow;
+
+// this provides marginal improvement in memory usage
+use single_vec::SingleVec;
+// use dummy_single_vec::SingleVec;
+
+use crate::model::stack_slots::{FramePointerEffect, StackPointerEffect, StackSlot};
+use crate::model::superset::{ExtraFeatures, RegisterSet, UsedRegister, MAX_OPERANDS};
+use crate::model::vocab::CodeVocab;
+use crate::model::{InstructionFeature, Label, SupersetConfig, SupersetSample};
+use anyhow::{anyhow, bail, Context};
+use arrayvec::ArrayVec;
+use enum_map::EnumMap;
+use iced_x86::{Code, Mnemonic};
+use itertools::{Either, Itertools};
+use ndarray::{Array, Array1, Array2, Dimension, Ix1, Ix2};
+use ndarray_npy::{NpzReader, NpzWriter, ReadableElement};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, Write};
+use std::ops::AddAssign;
+use strum::{EnumCount, EnumIter, EnumString, IntoEnumIterator, IntoStaticStr};
+
+#[derive(
+    Serialize,
+    Deserialize,
+    IntoPrimitive,
+    TryFromPrimitive,
+    EnumCount,
+    EnumIter,
+    EnumString,
+    IntoStaticStr,
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum RelationType {
+    Next = 0,
+    Previous = 1,
+    Overlap = 2,
+    JumpTo = 3,
+    JumpFrom = 4,
+    DataDependency = 5,
+    DataDependent = 6,
+    References = 7,
+    ReferencedBy = 8,
+    CallTo = 9,
+    CalledFrom = 10,
+    /// From a call to the instruction after it, where the callee returns to
+    ReturnSite = 11,
+    /// From a load from a stack slot to the stores of the value it reads
+    StackDependency = 12,
+    /// From a store to a stack slot to the loads reading it
+    StackDependent = 13,
+}
+
+/// Bumped each time the [`RelationType`] numbering changes, it's written to the npz metadata
+///
+/// 1. `Next` to `DataDependent`
+/// 2. `References` and `ReferencedBy`
+/// 3. `CallTo`, `CalledFrom` and `ReturnSite`, when `CallTo` is built the calls no longer make `JumpTo`, `JumpFrom` and `Next`
+/// 4. `StackDependency` and `StackDependent`
+pub const RELATION_VERSION: u32 = 4;
+
+// stores the indices of the latest definition of each tracked register, at its slot in `RegisterSlots`
+// it's empty until a definition reaches the node, so the nodes not walked yet don't allocate
+type DataDepState = Box<[SingleVec]>;
+
+/// The slots of the tracked registers in a [`DataDepState`], the other registers are ignored
+struct RegisterSlots {
+    slots: EnumMap<UsedRegister, Option<u8>>,
+    len: usize,
+}
+
+impl RegisterSlots {
+    fn new(tracked: RegisterSet) -> Self {
+        let mut slots = EnumMap::default();
+        let mut len = 0;
+        for reg in tracked.iter_used_registers() {
+            slots[reg] = Some(len as u8);
+            len += 1;
+        }
+        Self { slots, len }
+    }
+
+    fn get<'a>(&self, state: &'a DataDepState, reg: UsedRegister) -> Option<&'a SingleVec> {
+        state.get(self.slots[reg]? as usize)
+    }
+
+    fn get_mut<'a>(
+        &self,
+        state: &'a mut DataDepState,
+        reg: UsedRegister,
+    ) -> Option<&'a mut SingleVec> {
+        state.get_mut(self.slots[reg]? as usize)
+    }
+
+    fn allocate(&self, state: &mut DataDepState) {
+        if state.is_empty() {
+            *state = vec![SingleVec::default(); self.len].into_boxed_slice();
+        }
+    }
+}
+
+/// The stack and frame pointers as offsets from an origin shared along the path, and the latest stores to the slots
+#[derive(Clone, Default)]
+struct StackState {
+    stack_pointer: Option<i32>,
+    frame_pointer: Option<i32>,
+    slots: BTreeMap<i32, SingleVec>,
+    /// Instructions walked since the origin was set
+    depth: u32,
+}
+
+impl StackState {
+    fn slot_offset(&self, slot: StackSlot) -> Option<i32> {
+        match slot {
+            StackSlot::StackPointer(offset) => self.stack_pointer.map(|v| v.wrapping_add(offset)),
+            StackSlot::FramePointer(offset) => self.frame_pointer.map(|v| v.wrapping_add(offset)),
+        }
+    }
+
+    fn is_lost(&self) -> bool {
+        self.stack_pointer.is_none() && self.frame_pointer.is_none()
+    }
+}
+
+#[derive(Clone, Default)]
+struct WalkState {
+    registers: DataDepState,
+    // boxed, as it's only tracked with `SupersetConfig::stack_dependencies`
+    stack: Option<Box<StackState>>,
+}
+type Index32 = u32;
+// the superset addresses are 32-bit, but the jump targets can be anywhere in the 64-bit address space
+type Address64 = u64;
+
+fn get_instr_out_edges(
+    superset: &SupersetSample,
+    superset_index: &AddressIndex,
+    index: usize,
+) -> ArrayVec<Index32, 2> {
+    let addr = superset.addresses[index];
+    let instr = superset.instructions[index];
+    let next_addr = addr as Address64 + instr.size as Address64;
+
+    let out_edges = None
+        .into_iter()
+        // the next instruction
+        .chain(
+            instr
+                .falls_through
+                .then(|| superset_index.get(next_addr))
+                .flatten(),
+        )
+        // the jump target
+        .chain(
+            instr
+                .jump_target()
+                .and_then(|target| superset_index.get(target)),
+        )
+        .rev()
+        .collect();
+
+    out_edges
+}
+
+/// The instruction a call returns to, unless the call jumps right to it (like `call $+5` getting its address)
+fn get_return_site(
+    superset: &SupersetSample,
+    superset_index: &AddressIndex,
+    index: usize,
+) -> Option<Index32> {
+    let instr = superset.instructions[index];
+    if instr.code.mnemonic() != Mnemonic::Call || !instr.falls_through {
+        return None;
+    }
+    let next =
+        superset_index.get(superset.addresses[index] as Address64 + instr.size as Address64)?;
+    let target = instr
+        .jump_target()
+        .and_then(|target| superset_index.get(target));
+    (target != Some(next)).then_some(next)
+}
+
+fn toposort(superset: &SupersetSample, superset_index: &AddressIndex) -> Vec<Index32> {
+    struct BacktrackStackItem {
+        index: Index32,
+        iter: ArrayVec<Index32, 2>,
+    }
+
+    let len = superset.addresses.len();
+    let mut stack = Vec::new();
+    let mut is_in_stack = vec![false; len];
+    let mut was_visited = vec![false; len];
+    let mut result = Vec::new();
+
+    // TODO: maybe find a better ordering?
+    // currently, this results in just in 0..len being returned from the function
+    for start_index in (0..len).rev() {
+        if was_visited[start_index] {
+            continue;
+        }
+
+        stack.push(BacktrackStackItem {
+            index: start_index as Index32,
+            iter: get_instr_out_edges(superset, superset_index, start_index),
+        });
+
+        while let Some(mut item) = stack.pop() {
+            if let Some(next) = item.iter.pop() {
+                let index = item.index;
+
+                // push current item back to the stack
+                stack.push(item);
+
+                // ignore any back edges to guarantee acyclicity
+                if next <= index {
+                    continue;
+                }
+                // ignore already visited (and finalized) nodes
+                if was_visited[next as usize] {
+                    continue;
+                }
+
+                if is_in_stack[next as usize] {
+                    panic!("cycle detected, even though it should have been removed")
+                }
+                is_in_stack[next as usize] = true;
+
+                stack.push(BacktrackStackItem {
+                    index: next,
+                    iter: get_instr_out_edges(superset, superset_index, next as usize),
+                });
+            } else {
+                is_in_stack[item.index as usize] = false;
+                if !was_visited[item.index as usize] {
+                    was_visited[item.index as usize] = true;
+                    result.push(item.index);
+                } else {
+                    panic!("We have left the node twice?? (index: {})", item.index)
+                }
+            }
+        }
+    }
+
+    result.reverse();
+
+    result
+}
+
+/// How much the data dependency limits of [`GraphConfig`] pruned, it's written to the npz metadata
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DataDependencyStats {
+    /// The register and stack slot dependencies found by the walk, each is made into the relations that are kept
+    pub dependencies: u64,
+    /// Definitions dropped from the state of a node over [`GraphConfig::max_data_dependencies`]
+    ///
+    /// Each of them would have been linked to the uses of the register downstream
+    pub dropped_by_count: u64,
+    /// Definitions not propagated further than [`GraphConfig::max_data_dependency_distance`]
+    pub dropped_by_distance: u64,
+}
+
+impl AddAssign for DataDependencyStats {
+    fn add_assign(&mut self, other: Self) {
+        self.dependencies += other.dependencies;
+        self.dropped_by_count += other.dropped_by_count;
+        self.dropped_by_distance += other.dropped_by_distance;
+    }
+}
+
+/// Merges the definitions reaching a node, enforcing the limits
+struct DefinitionLimits<'a> {
+    addresses: &'a [u32],
+    max_definitions: usize,
+    max_distance: u64,
+    stats: DataDependencyStats,
+}
+
+impl DefinitionLimits<'_> {
+    fn distance(&self, a: Index32, b: Index32) -> u64 {
+        self.addresses[a as usize].abs_diff(self.addresses[b as usize]) as u64
+    }
+
+    fn merge(&mut self, src: &SingleVec, dst: &mut SingleVec, dst_index: Index32) {
+        for src in src.iter() {
+            if self.distance(src, dst_index) > self.max_distance {
+                self.stats.dropped_by_distance += 1;
+            } else if !dst.contains(src) {
+                dst.push(src);
+            }
+        }
+
+        if dst.len() > self.max_definitions {
+            // keep the closest ones, the index breaks the ties to keep it deterministic
+            let mut definitions = dst.iter().collect::<Vec<_>>();
+            definitions.sort_by_key(|&d| (self.distance(d, dst_index), d));
+            self.stats.dropped_by_count += (definitions.len() - self.max_definitions) as u64;
+            definitions.truncate(self.max_definitions);
+            *dst = SingleVec::from_vec(definitions);
+        }
+    }
+}
+
+// walk all simple paths using recursion (TODO: can this fail because of too much recursion?)
+fn walk_data_dep(
+    graph: &mut Graph,
+    superset: &SupersetSample,
+    superset_index: &AddressIndex,
+    config: &GraphConfig,
+) -> DataDependencyStats {
+    fn collect_edges(
+        graph: &mut Graph,
+        superset: &SupersetSample,
+        slots: &RegisterSlots,
+        index: usize,
+        state: &WalkState,
+        stats: &mut DataDependencyStats,
+    ) {
+        let instr = superset.instructions[index];
+        for used_reg in instr.uses.iter_used_registers() {
+            let Some(define_indices) = slots.get(&state.registers, used_reg) else {
+                continue;
+            };
+            for define_index in define_indices.iter() {
+                stats.dependencies += 1;
+                graph.add_edge(
+                    index as Index32,
+                    define_index as Index32,
+                    RelationType::DataDependency,
+                );
+                graph.add_edge(
+                    define_index as Index32,
+                    index as Index32,
+                    RelationType::DataDependent,
+                );
+            }
+        }
+
+        let (Some(effect), Some(stack)) =
+            (superset.stack_effects.get(index), state.stack.as_deref())
+        else {
+            return;
+        };
+        let Some(store_indices) = effect
+            .load
+            .and_then(|slot| stack.slot_offset(slot))
+            .and_then(|offset| stack.slots.get(&offset))
+        else {
+            return;
+        };
+        for store_index in store_indices.iter() {
+            stats.dependencies += 1;
+            graph.add_edge(
+                index as Index32,
+                store_index as Index32,
+                RelationType::StackDependency,
+            );
+            graph.add_edge(
+                store_index as Index32,
+                index as Index32,
+                RelationType::StackDependent,
+            );
+        }
+    }
+
+    /// `returned` applies the call as seen by its return site (see [`crate::model::stack_slots::StackEffect::after_return`])
+    fn apply_state(
+        superset: &SupersetSample,
+        slots: &RegisterSlots,
+        index: usize,
+        state: &mut WalkState,
+        returned: bool,
+    ) {
+        let instr = superset.instructions[index];
+        for killed_reg in instr.kills.iter_used_registers() {
+            if let Some(definitions) = slots.get_mut(&mut state.registers, killed_reg) {
+                definitions.clear();
+            }
+        }
+        if !instr.defines.is_empty() {
+            slots.allocate(&mut state.registers);
+        }
+        for defined_reg in instr.defines.iter_used_registers() {
+            if let Some(definitions) = slots.get_mut(&mut state.registers, defined_reg) {
+                *definitions = SingleVec::from_single(index as Index32);
+            }
+        }
+
+        let (Some(mut effect), Some(stack)) = (
+            superset.stack_effects.get(index).copied(),
+            state.stack.as_deref_mut(),
+        ) else {
+            return;
+        };
+        if returned {
+            effect = effect.after_return();
+        }
+        if let Some(offset) = effect.store.and_then(|slot| stack.slot_offset(slot)) {
+            stack
+                .slots
+                .insert(offset, SingleVec::from_single(index as Index32));
+        }
+        // both are computed from the values before the instruction
+        let stack_pointer = match effect.stack_pointer {
+            StackPointerEffect::Delta(delta) => stack.stack_pointer.map(|v| v.wrapping_add(delta)),
+            StackPointerEffect::FromFramePointer(offset) => {
+                stack.frame_pointer.map(|v| v.wrapping_add(offset))
+            }
+            StackPointerEffect::Unknown => None,
+        };
+        let frame_pointer = match effect.frame_pointer {
+            FramePointerEffect::Unchanged => stack.frame_pointer,
+            FramePointerEffect::FromStackPointer(offset) => {
+                stack.stack_pointer.map(|v| v.wrapping_add(offset))
+            }
+            FramePointerEffect::Unknown => None,
+        };
+        stack.stack_pointer = stack_pointer;
+        stack.frame_pointer = frame_pointer;
+        stack.depth = stack.depth.saturating_add(1);
+        if stack.is_lost() {
+            // no slot can be addressed anymore
+            stack.slots.clear();
+            stack.depth = 0;
+        }
+    }
+
+    fn aggregate_state(
+        state: &WalkState,
+        dst_state: &mut WalkState,
+        dst_index: Index32,
+        slots: &RegisterSlots,
+        limits: &mut DefinitionLimits,
+    ) {
+        if !state.registers.is_empty() {
+            slots.allocate(&mut dst_state.registers);
+        }
+        for (src, dst) in state.registers.iter().zip(dst_state.registers.iter_mut()) {
+            limits.merge(src, dst, dst_index);
+        }
+
+        let Some(src) = state.stack.as_deref() else {
+            return;
+        };
+        let Some(dst) = dst_state.stack.as_deref_mut() else {
+            dst_state.stack = Some(Box::new(src.clone()));
+            return;
+        };
+        // the paths can only be merged if they agree on one of the pointers, otherwise their origins differ
+        // in that case, the longer one is kept, as the overlapping superset paths rarely go far
+        let agrees = |a: Option<i32>, b: Option<i32>| a.is_some() && a == b;
+        if !agrees(dst.stack_pointer, src.stack_pointer)
+            && !agrees(dst.frame_pointer, src.frame_pointer)
+        {
+            if src.depth > dst.depth {
+                dst.clone_from(src);
+            }
+            return;
+        }
+        if dst.stack_pointer != src.stack_pointer {
+            dst.stack_pointer = None;
+        }
+        if dst.frame_pointer != src.frame_pointer {
+            dst.frame_pointer = None;
+        }
+        dst.depth = dst.depth.max(src.depth);
+        for (&offset, src) in src.slots.iter() {
+            limits.merge(src, dst.slots.entry(offset).or_default(), dst_index);
+        }
+    }
+
+    let topo_order = toposort(superset, superset_index);
+
+    let len = superset.addresses.len();
+    let mut states = vec![WalkState::default(); len];
+    let track_registers = config
+        .relations
+        .contains_any(&[RelationType::DataDependency, RelationType::DataDependent]);
+    let slots = RegisterSlots::new(if track_registers {
+        superset.config.register_classes.registers()
+    } else {
+        RegisterSet::empty()
+    });
+    let track_stack = superset.config.stack_dependencies
+        && config
+            .relations
+            .contains_any(&[RelationType::StackDependency, RelationType::StackDependent]);
+    let mut limits = DefinitionLimits {
+        addresses: &superset.addresses,
+        max_definitions: config
+            .max_data_dependencies
+            .map_or(usize::MAX, |v| v as usize),
+        max_distance: config
+            .max_data_dependency_distance
+            .map_or(u64::MAX, u64::from),
+        stats: DataDependencyStats::default(),
+    };
+
+    // walk the graph in topological order, collecting edges and updating the data dependency state
+    for index in topo_order {
+        if track_stack {
+            // the paths starting here (or where the pointers were lost) get a new origin
+            let stack = states[index as usize]
+                .stack
+                .get_or_insert_with(Default::default);
+            if stack.is_lost() {
+                **stack = StackState {
+                    stack_pointer: Some(0),
+                    ..Default::default()
+                };
+            }
+        }
+        let state = &states[index as usize];
+        collect_edges(
+            graph,
+            superset,
+            &slots,
+            index as usize,
+            state,
+            &mut limits.stats,
+        );
+        let mut state = state.clone();
+        // the return site of a call doesn't see the return address pushed for the callee
+        let return_site =
+            get_return_site(superset, superset_index, index as usize).filter(|_| track_stack);
+        let return_state = return_site.map(|_| {
+            let mut return_state = state.clone();
+            apply_state(superset, &slots, index as usize, &mut return_state, true);
+            return_state
+        });
+        apply_state(superset, &slots, index as usize, &mut state, false);
+        for succ in get_instr_out_edges(superset, superset_index, index as usize) {
+            let state = match &return_state {
+                Some(return_state) if return_site == Some(succ) => return_state,
+                _ => &state,
+            };
+            aggregate_state(state, &mut states[succ as usize], succ, &slots, &mut limits);
+        }
+        // we will never need this instr again, so we can clear the state
+        states[index as usize] = WalkState::default();
+    }
+
+    limits.stats
+}
+
+/// The relations from a branch to its target and back
+fn branch_relations(is_call: bool) -> (RelationType, RelationType) {
+    if is_call {
+        (RelationType::CallTo, RelationType::CalledFrom)
+    } else {
+        (RelationType::JumpTo, RelationType::JumpFrom)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Graph {
+    pub edges: Vec<(Index32, Index32)>,
+    pub edge_types: Vec<RelationType>,
+    /// How many times each edge was added, only filled by [`Graph::canonicalize`]
+    pub edge_multiplicities: Vec<u32>,
+    /// Whether [`Graph::add_edge`] keeps the edges of a relation type, indexed by the type
+    #[serde(skip)]
+    kept_relations: [bool; RelationType::COUNT],
+}
+
+impl Graph {
+    /// Only the edges of `relations` are stored, the others are dropped as they are added
+    pub fn new(relations: &RelationSet) -> Self {
+        Self {
+            edges: Vec::new(),
+            edge_types: Vec::new(),
+            edge_multiplicities: Vec::new(),
+            kept_relations: RelationType::iter()
+                .map(|t| relations.contains(t))
+                .collect::<Vec<_>>()
+                .try_into()
+                .unwrap(),
+        }
+    }
+
+    pub fn add_edge(&mut self, from: Index32, to: Index32, edge_type: RelationType) {
+        if self.kept_relations[edge_type as usize] {
+            self.edges.push((from, to));
+            self.edge_types.push(edge_type);
+        }
+    }
+
+    /// Sorts the edges by the source, the target and the type, merging the repeated ones
+    ///
+    /// The number of merged copies goes to [`Graph::edge_multiplicities`]
+    pub fn canonicalize(&mut self, drop_self_loops: bool) {
+        let mut edges = std::mem::take(&mut self.edges)
+            .into_iter()
+            .zip(std::mem::take(&mut self.edge_types))
+            .filter(|&((from, to), _)| !(drop_self_loops && from == to))
+            .map(|((from, to), edge_type)| (from, to, edge_type))
+            .collect::<Vec<_>>();
+        edges.sort_unstable();
+
+        self.edge_multiplicities.clear();
+        for (from, to, edge_type) in edges {
+            if self.edges.last() == Some(&(from, to)) && self.edge_types.last() == Some(&edge_type)
+            {
+                *self.edge_multiplicities.last_mut().unwrap() += 1;
+            } else {
+                self.edges.push((from, to));
+                self.edge_types.push(edge_type);
+                self.edge_multiplicities.push(1);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GraphSample {
+    pub addresses: Vec<u32>,
+    pub instructions: Vec<InstructionFeature>,
+    pub labels: Vec<Option<Label>>,
+    pub is_function_start: Vec<bool>,
+    pub data_hints: Vec<bool>,
+    pub bytes: Vec<u8>,
+    /// The [`memory_image::Protection`] bits of the memory region the node is in
+    pub protections: Vec<u8>,
+    /// Only computed if requested by [`SupersetConfig::extra_features`], otherwise empty
+    pub extra: Vec<ExtraFeatures>,
+    /// Node index and the address it references, sorted by the node
+    pub references: Vec<(Index32, u32)>,
+    // stores the graph, using indices into superset
+    pub graph: Graph,
+    pub superset_config: SupersetConfig,
+    pub graph_config: GraphConfig,
+    pub data_dependency_stats: DataDependencyStats,
+    /// Only set for the graphs of a window of the superset
+    pub window: Option<GraphWindow>,
+}
+
+/// The core addresses of a window graph, see [`GraphWindow`]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WindowBounds {
+    pub start: u32,
+    pub end: u32,
+    pub halo: u32,
+}
+
+/// Written to the npz as JSON, so that the consumers know how the graph was made
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GraphMetadata {
+    pub sample_hash: String,
+    /// See [`CodeVocab::hash`], the instruction codes are only meaningful with the same vocab
+    pub vocab_hash: String,
+    pub superset_config: SupersetConfig,
+    pub graph_config: GraphConfig,
+    pub data_dependency_stats: DataDependencyStats,
+    pub window: Option<WindowBounds>,
+    /// The relation types are listed, so that the models can be sized for them
+    pub relation_count: usize,
+    pub relation_version: u32,
+    pub relation_types: Vec<String>,
+}
+
+/// A graph read back by [`GraphSample::from_npz`]
+///
+/// Only the vocab indices of the instructions are stored, so it can't be a [`GraphSample`] again
+pub struct GraphNpz {
+    pub node_addresses: Vec<u32>,
+    pub instruction_sizes: Vec<u8>,
+    /// Indices into the vocab the graph was exported with
+    pub instruction_codes: Vec<u32>,
+    /// Only present if all the nodes are labeled
+    pub labels: Option<Vec<Label>>,
+    pub is_function_start: Vec<bool>,
+    pub graph: Graph,
+    pub metadata: GraphMetadata,
+}
+
+/// Finds an array by the name it was added with, the `.npy` extension of the archive entries is optional
+fn read_npz_array<R: Read + Seek, A: ReadableElement, D: Dimension>(
+    npz: &mut NpzReader<R>,
+    names: &[String],
+    name: &str,
+) -> anyhow::Result<Option<Array<A, D>>> {
+    let Some(entry) = names
+        .iter()
+        .find(|n| n.strip_suffix(".npy").unwrap_or(n) == name)
+    else {
+        return Ok(None);
+    };
+    Ok(Some(npz.by_name(entry)?))
+}
+
+impl GraphSample {
+    pub fn new(superset: SupersetSample, config: &GraphConfig) -> Self {
+        assert!(superset.addresses.len() < i32::MAX as usize);
+
+        let mut graph = Graph::new(&config.relations);
+
+        let index = AddressIndex::new(&superset.addresses);
+
+        let mut data_dependency_stats = DataDependencyStats::default();
+        if config.relations.contains_any(&[
+            RelationType::DataDependency,
+            RelationType::DataDependent,
+            RelationType::StackDependency,
+            RelationType::StackDependent,
+        ]) {
+            data_dependency_stats = walk_data_dep(&mut graph, &superset, &index, config);
+        }
+
+        // without them the calls are jumps that fall through, like in the graphs of version 1
+        let call_relations = config.relations.contains(RelationType::CallTo);
+        let overlaps = config.relations.contains(RelationType::Overlap);
+
+        for (i, (&addr, instr)) in superset
+            .addresses
+            .iter()
+            .zip(superset.instructions.iter())
+            .enumerate()
+        {
+            let i = i as Index32;
+            let is_call = call_relations && instr.code.mnemonic() == Mnemonic::Call;
+            if instr.falls_through {
+                let addr = addr as Address64;
+                let next_addr = addr + instr.size as Address64;
+                if let Some(next) = index.get(next_addr) {
+                    if is_call {
+                        graph.add_edge(i, next, RelationType::ReturnSite);
+                    } else {
+                        graph.add_edge(i, next, RelationType::Next);
+                    }
+                    graph.add_edge(next, i, RelationType::Previous);
+                }
+
+                if overlaps {
+                    for j in addr + 1..next_addr {
+                        if let Some(overlap) = index.get(j) {
+                            graph.add_edge(i, overlap, RelationType::Overlap);
+                            graph.add_edge(overlap, i, RelationType::Overlap);
+                        }
+                    }
+                }
+            }
+
+            if let Some(target) = instr.jump_target() {
+                if let Some(jump) = index.get(target) {
+                    let (to, from) = branch_relations(is_call);
+                    graph.add_edge(i, jump, to);
+                    graph.add_edge(jump, i, from);
+                }
+            }
+        }
+
+        for table in superset.jump_tables.iter() {
+            let Some(i) = index.get(table.source as Address64) else {
+                continue;
+            };
+            let is_call = call_relations
+                && superset.instructions[i as usize].code.mnemonic() == Mnemonic::Call;
+            let (to, from) = branch_relations(is_call);
+            for &target in table.targets.iter().unique() {
+                if let Some(jump) = index.get(target as Address64) {
+                    graph.add_edge(i, jump, to);
+                    graph.add_edge(jump, i, from);
+                }
+            }
+        }
+
+        let references = superset
+            .references
+            .iter()
+            .filter_map(|r| Some((index.get(r.source as Address64)?, r.target)))
+            .collect();
+        for reference in superset.references.iter() {
+            let (Some(i), Some(target)) = (
+                index.get(reference.source as Address64),
+                index.get(reference.target as Address64),
+            ) else {
+                continue;
+            };
+            graph.add_edge(i, target, RelationType::References);
+            graph.add_edge(target, i, RelationType::ReferencedBy);
+        }
+
+        graph.canonicalize(config.drop_self_loops);
+
+        Self {
+            instructions: superset.instructions,
+            labels: superset.labels,
+            is_function_start: superset.is_function_start,
+            data_hints: superset.data_hints,
+            bytes: superset.bytes,
+            protections: superset.protections,
+            extra: superset.extra,
+            references,
+            graph,
+            superset_config: superset.config,
+            graph_config: config.clone(),
+            data_dependency_stats,
+            window: None,
+            addresses: superset.addresses,
+        }
+    }
+
+    /// Builds a graph per window of [`GraphConfig::window_size`] bytes (or a single graph without it)
+    ///
+    /// The windows are built one at a time, so that only one of them is in memory
+    pub fn new_windowed(
+        superset: SupersetSample,
+        config: &GraphConfig,
+    ) -> impl Iterator<Item = GraphSample> + '_ {
+        let Some(size) = config.window_size else {
+            return Either::Left(std::iter::once_with(move || {
+                GraphSample::new(superset, config)
+            }));
+        };
+        let windows = window::plan_windows(&superset, size);
+        Either::Right(windows.into_iter().map(move |core| {
+            let (window_superset, window) = window::cut_window(&superset, core, config.window_halo);
+            let mut graph = GraphSample::new(window_superset, config);
+            graph.window = Some(window);
+            graph
+        }))
+    }
+
+    /// `sample_hash` is the [`crate::model::ExecutableSample::hash`] of the sample the graph was made from
+    pub fn to_npz<W: Write + Seek>(
+        self,
+        vocab: &CodeVocab,
+        sample_hash: &str,
+        writer: W,
+    ) -> anyhow::Result<()> {
+        // let mut writer = zstd::stream::Encoder::new(
+        //     writer, 6, /* tuned to be not too big (file), not too slow (compression) */
+        // )?;
+
+        // TODO: this is clearly not the most efficient solution, but it works ig
+
+        // encode instructions
+
+        let instruction_sizes = Array1::from_iter(self.instructions.iter().map(
+            |i| i.size - 1, /* substraction is to make it 0-indexed class index */
+        ));
+        let instruction_codes =
+            Array1::from_iter(self.instructions.iter().map(|i| vocab[i.code] as i32));
+        let (instruction_labels, instruction_label_classes) =
+            if self.labels.iter().all(|l| l.is_some()) {
+                (
+                    Some(Array1::from_iter(
+                        self.labels.iter().map(|l| l.unwrap().is_code() as u8),
+                    )),
+                    Some(Array1::from_iter(
+                        self.labels.iter().map(|l| l.unwrap() as u8),
+                    )),
+                )
+            } else {
+                (None, None)
+            };
+
+        let instruction_function_starts =
+            Array1::from_iter(self.is_function_start.iter().map(|&v| v as u8));
+        let features = self.graph_config.node_features;
+        let instruction_references = (self.superset_config.has_references()
+            && features.contains(NodeFeatures::REFERENCES))
+        .then(|| {
+            // a row per node, as an instruction can have both an immediate and a displacement reference
+            let by_node = self
+                .references
+                .iter()
+                .unique()
+                .group_by(|&&(index, _)| index);
+            let by_node = by_node
+                .into_iter()
+                .map(|(index, group)| (index, group.map(|&(_, target)| target).collect_vec()))
+                .collect_vec();
+            let width = by_node.iter().map(|(_, t)| t.len()).max().unwrap_or(1);
+            let mut references = Array2::from_elem((self.instructions.len(), width), -1i64);
+            for (index, targets) in by_node {
+                for (column, target) in targets.into_iter().enumerate() {
+                    references[[index as usize, column]] = target as i64;
+                }
+            }
+            references
+        });
+        let instruction_data_hints = (self.superset_config.jump_tables
+            && features.contains(NodeFeatures::DATA_HINTS))
+        .then(|| Array1::from_iter(self.data_hints.iter().map(|&v| v as u8)));
+
+        let extra = (self.superset_config.extra_features && features.contains(NodeFeatures::EXTRA))
+            .then_some(&self.extra);
+        let extra_arrays = extra.map(|extra| {
+            (
+                // 0 means no operand, otherwise it's the OpKind + 1
+                Array2::from_shape_vec(
+                    (extra.len(), MAX_OPERANDS),
+                    extra
+                        .iter()
+                        .flat_map(|e| e.operand_kinds.map(|k| k.map_or(0, |k| k as u8 + 1)))
+                        .collect(),
+                )
+                .unwrap(),
+                Array1::from_iter(extra.iter().map(|e| e.prefixes.bits())),
+                Array1::from_iter(extra.iter().map(|e| e.flags.bits())),
+                Array1::from_iter(extra.iter().map(|e| e.memory_reads.bits())),
+                Array1::from_iter(extra.iter().map(|e| e.memory_writes.bits())),
+                Array1::from_iter(extra.iter().map(|e| e.stack_pointer_delta)),
+            )
+        });
+
+        let control_flow = features.contains(NodeFeatures::CONTROL_FLOW).then(|| {
+            (
+                Array1::from_iter(self.instructions.iter().map(|i| i.falls_through as u8)),
+                Array1::from_iter(
+                    self.instructions
+                        .iter()
+                        .map(|i| i.jump_target().is_some() as u8),
+                ),
+            )
+        });
+        let registers = features.contains(NodeFeatures::REGISTERS).then(|| {
+            (
+                Array1::from_iter(self.instructions.iter().map(|i| i.uses.bits())),
+                Array1::from_iter(self.instructions.iter().map(|i| i.defines.bits())),
+            )
+        });
+        let is_invalid = features.contains(NodeFeatures::INVALID).then(|| {
+            Array1::from_iter(
+                self.instructions
+                    .iter()
+                    .map(|i| (i.code == Code::INVALID) as u8),
+            )
+        });
+        let is_privileged = features.contains(NodeFeatures::PRIVILEGED).then(|| {
+            Array1::from_iter(
+                self.instructions
+                    .iter()
+                    .map(|i| i.code.is_privileged() as u8),
+            )
+        });
+        let protections = features
+            .contains(NodeFeatures::PROTECTION)
+            .then(|| Array1::from_vec(self.protections));
+        let bytes = features
+            .contains(NodeFeatures::BYTES)
+            .then(|| Array1::from_vec(self.bytes));
+
+        let node_addresses = Array1::from_vec(self.addresses);
+
+        drop(self.instructions);
+        drop(self.labels);
+
+        // encode relations
+        let relation_types =
+            Array1::from_iter(self.graph.edge_types.into_iter().map(|t| u8::from(t)));
+        let relation_multiplicities =
+            Array1::from_iter(self.graph.edge_multiplicities.into_iter().map(|m| m as i32));
+        let relations = Array2::from_shape_vec(
+            (self.graph.edges.len(), 2),
+            self.graph
+                .edges
+                .into_iter()
+                .flat_map(|(a, b)| [a as i32, b as i32])
+                .collect(),
+        )
+        .unwrap();
+
+        let relation_count = self.graph_config.relations.relation_count();
+        let metadata = Array1::from_vec(serde_json::to_vec(&GraphMetadata {
+            sample_hash: sample_hash.to_string(),
+            vocab_hash: vocab.hash(),
+            superset_config: self.superset_config,
+            graph_config: self.graph_config,
+            data_dependency_stats: self.data_dependency_stats,
+            window: self.window.as_ref().map(|w| WindowBounds {
+                start: w.core.start(),
+                end: w.core.end(),
+                halo: w.halo,
+            }),
+            relation_count,
+            relation_version: RELATION_VERSION,
+            relation_types: RelationType::iter()
+                .map(|r| <&str>::from(r).to_string())
+                .collect(),
+        })?);
+
+        // the node indices in the whole superset, to stitch the predictions of the windows together
+        let window_arrays = self.window.map(|w| {
+            (
+                Array1::from_iter(w.node_indices.iter().map(|&i| i as i32)),
+                Array1::from_iter(w.is_core.iter().map(|&v| v as u8)),
+            )
+        });
+
+        let mut npz = NpzWriter::new_zstd_compressed(writer, Some(6));
+        npz.add_array("node_addresses", &node_addresses)?;
+        npz.add_array("instruction_sizes", &instruction_sizes)?;
+        npz.add_array("instruction_codes", &instruction_codes)?;
+        if let Some(instruction_labels) = instruction_labels {
+            npz.add_array("instruction_labels", &instruction_labels)?;
+        }
+        if let Some(instruction_label_classes) = instruction_label_classes {
+            npz.add_array("instruction_label_classes", &instruction_label_classes)?;
+        }
+        npz.add_array("instruction_function_starts", &instruction_function_starts)?;
+        if let Some(instruction_references) = instruction_references {
+            npz.add_array("instruction_references", &instruction_references)?;
+        }
+        if let Some(instruction_data_hints) = instruction_data_hints {
+            npz.add_array("instruction_data_hints", &instruction_data_hints)?;
+        }
+        if let Some((operand_kinds, prefixes, flags, memory_reads, memory_writes, sp_deltas)) =
+            extra_arrays
+        {
+            npz.add_array("instruction_operand_kinds", &operand_kinds)?;
+            npz.add_array("instruction_prefixes", &prefixes)?;
+            npz.add_array("instruction_flags", &flags)?;
+            npz.add_array("instruction_memory_reads", &memory_reads)?;
+            npz.add_array("instruction_memory_writes", &memory_writes)?;
+            npz.add_array("instruction_stack_pointer_deltas", &sp_deltas)?;
+        }
+        if let Some((falls_through, has_jump_target)) = control_flow {
+            npz.add_array("instruction_falls_through", &falls_through)?;
+            npz.add_array("instruction_has_jump_target", &has_jump_target)?;
+        }
+        if let Some((uses, defines)) = registers {
+            npz.add_array("instruction_uses", &uses)?;
+            npz.add_array("instruction_defines", &defines)?;
+        }
+        if let Some(is_invalid) = is_invalid {
+            npz.add_array("instruction_is_invalid", &is_invalid)?;
+        }
+        if let Some(is_privileged) = is_privileged {
+            npz.add_array("instruction_is_privileged", &is_privileged)?;
+        }
+        if let Some(protections) = protections {
+            npz.add_array("node_protections", &protections)?;
+        }
+        if let Some(bytes) = bytes {
+            npz.add_array("node_bytes", &bytes)?;
+        }
+        if let Some((node_indices, is_core)) = window_arrays {
+            npz.add_array("window_node_indices", &node_indices)?;
+            npz.add_array("window_node_is_core", &is_core)?;
+        }
+        npz.add_array("relation_types", &relation_types)?;
+        npz.add_array("relations", &relations)?;
+        npz.add_array("relation_multiplicities", &relation_multiplicities)?;
+        npz.add_array("metadata", &metadata)?;
+        npz.finish()?;
+
+        Ok(())
+    }
+
+    /// Reads the node arrays, the relations and the metadata written by [`Self::to_npz`]
+    ///
+    /// The optional node features are skipped
+    pub fn from_npz<R: Read + Seek>(reader: R) -> anyhow::Result<GraphNpz> {
+        let mut npz = NpzReader::new(reader)?;
+        let names = npz.names()?;
+
+        let metadata: GraphMetadata = serde_json::from_slice(
+            read_npz_array::<_, u8, Ix1>(&mut npz, &names, "metadata")?
+                .context("The npz has no metadata")?
+                .as_slice()
+                .unwrap(),
+        )?;
+        // the relation ids are only ever appended, so the older graphs can be read as they are
+        if metadata.relation_version > RELATION_VERSION {
+            bail!(
+                "The graph was made with the relation version {}, newer than {}",
+                metadata.relation_version,
+                RELATION_VERSION
+            );
+        }
+
+        let node_addresses: Vec<u32> =
+            read_npz_array::<_, u32, Ix1>(&mut npz, &names, "node_addresses")?
+                .context("The npz has no node addresses")?
+                .to_vec();
+        let instruction_sizes: Vec<u8> =
+            read_npz_array::<_, u8, Ix1>(&mut npz, &names, "instruction_sizes")?
+                .context("The npz has no instruction sizes")?
+                .iter()
+                .map(|&s| s.checked_add(1).context("Invalid instruction size"))
+                .collect::<anyhow::Result<_>>()?;
+        let instruction_codes =
+            read_npz_array::<_, i32, Ix1>(&mut npz, &names, "instruction_codes")?
+                .context("The npz has no instruction codes")?
+                .iter()
+                .map(|&c| c as u32)
+                .collect();
+        let labels = read_npz_array::<_, u8, Ix1>(&mut npz, &names, "instruction_label_classes")?
+            .map(|labels| {
+                labels
+                    .iter()
+                    .map(|&l| Label::try_from(l).map_err(|_| anyhow!("Invalid label {}", l)))
+                    .collect::<anyhow::Result<Vec<_>>>()
+            })
+            .transpose()?;
+        let is_function_start =
+            read_npz_array::<_, u8, Ix1>(&mut npz, &names, "instruction_function_starts")?
+                .context("The npz has no function starts")?
+                .iter()
+                .map(|&v| v != 0)
+                .collect();
+
+        let edge_types = read_npz_array::<_, u8, Ix1>(&mut npz, &names, "relation_types")?
+            .context("The npz has no relation types")?
+            .iter()
+            .map(|&t| RelationType::try_from(t).map_err(|_| anyhow!("Invalid relation type {}", t)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let edge_multiplicities =
+            read_npz_array::<_, i32, Ix1>(&mut npz, &names, "relation_multiplicities")?
+                .map(|m| m.iter().map(|&m| m as u32).collect())
+                .unwrap_or_default();
+        let relations = read_npz_array::<_, i32, Ix2>(&mut npz, &names, "relations")?
+            .context("The npz has no relations")?;
+        let edges = relations
+            .outer_iter()
+            .map(|edge| (edge[0] as Index32, edge[1] as Index32))
+            .collect::<Vec<_>>();
+
+        let nodes = node_addresses.len();
+        if instruction_sizes.len() != nodes
+            || edges.len() != edge_types.len()
+            || edges
+                .iter()
+                .any(|&(a, b)| a as usize >= nodes || b as usize >= nodes)
+        {
+            bail!("The graph arrays are inconsistent");
+        }
+
+        Ok(GraphNpz {
+            node_addresses,
+            instruction_sizes,
+            instruction_codes,
+            labels,
+            is_function_start,
+            graph: Graph {
+                edges,
+                edge_types,
+                edge_multiplicities,
+                kept_relations: RelationType::iter()
+                    .map(|t| metadata.graph_config.relations.contains(t))
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .unwrap(),
+            },
+            metadata,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::superset::{RegisterClasses, RegisterSet};
+    use crate::model::{AddressClasses, ExecutableSample};
+    use memory_image::{MemoryImage, Protection};
+
+    pub(super) fn memory_superset(memory: MemoryImage, config: &SupersetConfig) -> SupersetSample {
+        ExecutableSample::new(memory, AddressClasses::new())
+            .unwrap()
+            .into_superset_with_config(config)
+    }
+
+    /// The superset of `code` mapped at address 0
+    pub(super) fn superset(code: &[u8], config: &SupersetConfig) -> SupersetSample {
+        let mut memory = MemoryImage::new();
+        memory.add_region(0, Protection::READ_EXECUTE, code.to_vec(), "".to_string());
+        memory_superset(memory, config)
+    }
+
+    fn graph(code: &[u8]) -> GraphSample {
+        superset(code, &SupersetConfig::default()).into_graph(&GraphConfig::default())
+    }
+
+    fn has_edge(graph: &GraphSample, from: Index32, to: Index32, ty: RelationType) -> bool {
+        graph
+            .graph
+            .edges
+            .iter()
+            .zip(graph.graph.edge_types.iter())
+            .any(|(&edge, &edge_ty)| edge == (from, to) && edge_ty == ty)
+    }
+
+    #[test]
+    fn test_undefined_flags_kill() {
+        #[rustfmt::skip]
+        let code = [
+            0x39, 0xd8, // cmp eax, ebx
+            0x90, 0x90, // nop; nop
+            0x74, 0x00, // jz +0
+        ];
+        let g = graph(&code);
+        assert!(has_edge(&g, 4, 0, RelationType::DataDependency));
+
+        #[rustfmt::skip]
+        let code = [
+            0x39, 0xd8, // cmp eax, ebx
+            0xf7, 0xe1, // mul ecx (leaves ZF undefined)
+            0x74, 0x00, // jz +0
+        ];
+        let g = graph(&code);
+        assert!(!has_edge(&g, 4, 0, RelationType::DataDependency));
+        assert!(!has_edge(&g, 4, 2, RelationType::DataDependency));
+    }
+
+    #[test]
+    fn test_register_classes() {
+        #[rustfmt::skip]
+        let code = [
+            0x0f, 0x28, 0xc1,             // movaps xmm0, xmm1
+            0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+            0x0f, 0x58, 0xd0,             // addps xmm2, xmm0
+            0x01, 0xc3,                   // add ebx, eax
+        ];
+        let g = graph(&code);
+        assert!(has_edge(&g, 11, 3, RelationType::DataDependency));
+        assert!(!has_edge(&g, 8, 0, RelationType::DataDependency));
+
+        let config = SupersetConfig {
+            register_classes: RegisterClasses::all(),
+            ..Default::default()
+        };
+        let g = superset(&code, &config).into_graph(&GraphConfig::default());
+        assert!(has_edge(&g, 11, 3, RelationType::DataDependency));
+        assert!(has_edge(&g, 8, 0, RelationType::DataDependency));
+    }
+
+    #[test]
+    fn test_jump_table_edges() {
+        #[rustfmt::skip]
+        let code = vec![
+            0xff, 0x24, 0x85, 0x00, 0x20, 0x00, 0x00, // jmp [eax*4+0x2000]
+            0x90,                                     // nop
+            0xc3,                                     // ret
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        let table = [0x1007u32, 0x1008, 0x1007]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        memory.add_region(0x2000, Protection::READ, table, "".to_string());
+        let config = SupersetConfig {
+            jump_tables: true,
+            ..Default::default()
+        };
+        let g = memory_superset(memory, &config).into_graph(&GraphConfig::default());
+
+        assert!(has_edge(&g, 0, 7, RelationType::JumpTo));
+        assert!(has_edge(&g, 8, 0, RelationType::JumpFrom));
+        // the repeated entry doesn't make a second edge
+        let jumps = g
+            .graph
+            .edge_types
+            .iter()
+            .filter(|&&ty| ty == RelationType::JumpTo)
+            .count();
+        assert_eq!(jumps, 2);
+
+        // the table bytes come after the code
+        assert!(!g.data_hints[..9].iter().any(|&v| v));
+        assert!(g.data_hints[9..].iter().all(|&v| v));
+    }
+
+    #[test]
+    fn test_call_relations() {
+        #[rustfmt::skip]
+        let code = [
+            0xe8, 0x02, 0x00, 0x00, 0x00, // call 0x7
+            0x90,                         // nop
+            0xc3,                         // ret
+            0xc3,                         // ret
+        ];
+        let superset = superset(&code, &SupersetConfig::default());
+        let g = superset.clone().into_graph(&GraphConfig {
+            relations: RelationSet::all(),
+            ..Default::default()
+        });
+        assert!(has_edge(&g, 0, 7, RelationType::CallTo));
+        assert!(has_edge(&g, 7, 0, RelationType::CalledFrom));
+        assert!(has_edge(&g, 0, 5, RelationType::ReturnSite));
+        assert!(has_edge(&g, 5, 0, RelationType::Previous));
+        assert!(!has_edge(&g, 0, 7, RelationType::JumpTo));
+        assert!(!has_edge(&g, 0, 5, RelationType::Next));
+        assert!(has_edge(&g, 5, 6, RelationType::Next));
+
+        // the version 1 relations treat the calls as jumps
+        let g = superset.into_graph(&GraphConfig::default());
+        assert!(has_edge(&g, 0, 7, RelationType::JumpTo));
+        assert!(has_edge(&g, 7, 0, RelationType::JumpFrom));
+        assert!(has_edge(&g, 0, 5, RelationType::Next));
+        assert!(has_edge(&g, 5, 0, RelationType::Previous));
+    }
+
+    #[test]
+    fn test_stack_dependencies() {
+        #[rustfmt::skip]
+        let code = [
+            0x55,             // push ebp
+            0x89, 0xe5,       // mov ebp, esp
+            0x83, 0xec, 0x08, // sub esp, 8
+            0x89, 0x45, 0xfc, // mov [ebp-4], eax
+            0x51,             // push ecx
+            0x8b, 0x4d, 0xfc, // mov ecx, [ebp-4]
+            0x59,             // pop ecx
+            0xc9,             // leave
+            0xc3,             // ret
+        ];
+        let config = SupersetConfig {
+            stack_dependencies: true,
+            ..Default::default()
+        };
+        let g = superset(&code, &config).into_graph(&GraphConfig {
+            relations: RelationSet::all(),
+            ..Default::default()
+        });
+
+        assert!(has_edge(&g, 10, 6, RelationType::StackDependency));
+        assert!(has_edge(&g, 6, 10, RelationType::StackDependent));
+        assert!(has_edge(&g, 13, 9, RelationType::StackDependency));
+        assert!(!has_edge(&g, 10, 9, RelationType::StackDependency));
+        // the return address slot was never written to in this function
+        assert!(!(0..15).any(|i| has_edge(&g, 15, i, RelationType::StackDependency)));
+
+        // off by default
+        let g = graph(&code);
+        assert!(!g.graph.edge_types.contains(&RelationType::StackDependency));
+    }
+
+    #[test]
+    fn test_stack_dependencies_across_call() {
+        #[rustfmt::skip]
+        let code = [
+            0x89, 0x04, 0x24,             // mov [esp], eax
+            0xe8, 0x04, 0x00, 0x00, 0x00, // call 0xc
+            0x8b, 0x0c, 0x24,             // mov ecx, [esp]
+            0xc3,                         // ret
+            0x8b, 0x14, 0x24,             // mov edx, [esp]
+            0xc3,                         // ret
+        ];
+        let config = SupersetConfig {
+            stack_dependencies: true,
+            ..Default::default()
+        };
+        let g = superset(&code, &config).into_graph(&GraphConfig {
+            relations: RelationSet::all(),
+            ..Default::default()
+        });
+
+        // the return address is popped by the callee, so the slot still has the value stored before the call
+        assert!(has_edge(&g, 8, 0, RelationType::StackDependency));
+        assert!(!has_edge(&g, 8, 3, RelationType::StackDependency));
+        // while the callee reads the return address
+        assert!(has_edge(&g, 12, 3, RelationType::StackDependency));
+        assert!(!has_edge(&g, 12, 0, RelationType::StackDependency));
+    }
+
+    #[test]
+    fn test_graph_config() {
+        #[rustfmt::skip]
+        let code = [
+            0x31, 0xc0,                   // xor eax, eax
+            0x74, 0x05,                   // jz +5
+            0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+            0x50,                         // push eax
+        ];
+        let superset = superset(&code, &SupersetConfig::default());
+        let dependencies = |g: &GraphSample| {
+            g.graph
+                .edges
+                .iter()
+                .zip(g.graph.edge_types.iter())
+                .filter(|&(&(from, _), &ty)| from == 9 && ty == RelationType::DataDependency)
+                .count()
+        };
+
+        // `push eax` reads the eax defined by either `xor` or `mov`
+        let g = superset.clone().into_graph(&GraphConfig::default());
+        assert!(has_edge(&g, 4, 5, RelationType::Overlap));
+        assert!(dependencies(&g) > 1);
+
+        let config = GraphConfig {
+            relations: "next,previous,data_dependency".parse().unwrap(),
+            max_data_dependencies: Some(1),
+            ..Default::default()
+        };
+        let g = superset.clone().into_graph(&config);
+        assert!(g
+            .graph
+            .edge_types
+            .iter()
+            .all(|&t| config.relations.contains(t)));
+        assert!(has_edge(&g, 4, 9, RelationType::Next));
+        assert_eq!(dependencies(&g), 1);
+        // the closer definition is kept
+        assert!(has_edge(&g, 9, 4, RelationType::DataDependency));
+        assert!(g.data_dependency_stats.dropped_by_count > 0);
+        assert_eq!(g.data_dependency_stats.dropped_by_distance, 0);
+        // the dependencies are counted even though `DataDependent` isn't built
+        assert_eq!(
+            g.data_dependency_stats.dependencies as usize,
+            g.graph
+                .edge_types
+                .iter()
+                .filter(|&&t| t == RelationType::DataDependency)
+                .count()
+        );
+
+        let config = GraphConfig {
+            max_data_dependency_distance: Some(6),
+            ..Default::default()
+        };
+        let g = superset.clone().into_graph(&config);
+        assert!(has_edge(&g, 9, 4, RelationType::DataDependency));
+        assert!(!has_edge(&g, 9, 0, RelationType::DataDependency));
+        assert!(g.data_dependency_stats.dropped_by_distance > 0);
+        assert_eq!(
+            g.data_dependency_stats.dependencies as usize * 2,
+            g.graph
+                .edge_types
+                .iter()
+                .filter(|&&t| matches!(
+                    t,
+                    RelationType::DataDependency | RelationType::DataDependent
+                ))
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_canonical_edges() {
+        #[rustfmt::skip]
+        let code = [
+            0x87, 0xd8, // xchg eax, ebx
+            0x01, 0xd8, // add eax, ebx
+            0xeb, 0xfe, // jmp $
+        ];
+        let superset = superset(&code, &SupersetConfig::default());
+        let check_invariants = |g: &GraphSample| {
+            let graph = &g.graph;
+            assert_eq!(graph.edges.len(), graph.edge_types.len());
+            assert_eq!(graph.edges.len(), graph.edge_multiplicities.len());
+            assert!(graph.edge_multiplicities.iter().all(|&m| m > 0));
+            let keys = graph
+                .edges
+                .iter()
+                .zip(graph.edge_types.iter())
+                .collect::<Vec<_>>();
+            assert!(
+                keys.windows(2).all(|w| w[0] < w[1]),
+                "not sorted or repeated"
+            );
+            assert!(!keys
+                .iter()
+                .any(|&(&(from, to), &ty)| from == to && ty == RelationType::Overlap));
+        };
+        let multiplicity = |g: &GraphSample, edge: (Index32, Index32), ty: RelationType| {
+            let i = g
+                .graph
+                .edges
+                .iter()
+                .zip(g.graph.edge_types.iter())
+                .position(|(&e, &t)| e == edge && t == ty)
+                .unwrap();
+            g.graph.edge_multiplicities[i]
+        };
+
+        let g = superset.clone().into_graph(&GraphConfig::default());
+        check_invariants(&g);
+        // `add` depends on `xchg` through both eax and ebx
+        assert_eq!(multiplicity(&g, (2, 0), RelationType::DataDependency), 2);
+        assert_eq!(multiplicity(&g, (0, 2), RelationType::DataDependent), 2);
+        assert_eq!(multiplicity(&g, (0, 1), RelationType::Overlap), 1);
+        assert!(has_edge(&g, 4, 4, RelationType::JumpTo));
+
+        let config = GraphConfig {
+            drop_self_loops: true,
+            ..Default::default()
+        };
+        let g = superset.clone().into_graph(&config);
+        check_invariants(&g);
+        assert!(!g.graph.edges.iter().any(|&(from, to)| from == to));
+    }
+
+    #[test]
+    fn test_npz_round_trip() {
+        #[rustfmt::skip]
+        let code = [
+            0x31, 0xc0, // xor eax, eax
+            0x74, 0x01, // jz +1
+            0x50,       // push eax
+            0xc3,       // ret
+        ];
+        let graph = superset(&code, &SupersetConfig::default()).into_graph(&GraphConfig::default());
+        let sample_hash = "0123456789abcdef";
+        let vocab = CodeVocab::new(vec![iced_x86::Code::Xor_rm32_r32, iced_x86::Code::Push_r32]);
+
+        let addresses = graph.addresses.clone();
+        let sizes = graph
+            .instructions
+            .iter()
+            .map(|i| i.size)
+            .collect::<Vec<_>>();
+        let codes = graph
+            .instructions
+            .iter()
+            .map(|i| vocab[i.code] as u32)
+            .collect::<Vec<_>>();
+        let labels = graph.labels.iter().map(|l| l.unwrap()).collect::<Vec<_>>();
+        let edges = graph.graph.edges.clone();
+        let edge_types = graph.graph.edge_types.clone();
+        let edge_multiplicities = graph.graph.edge_multiplicities.clone();
+        let data_dependency_stats = graph.data_dependency_stats;
+
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        graph.to_npz(&vocab, sample_hash, &mut buffer).unwrap();
+        buffer.set_position(0);
+        let read = GraphSample::from_npz(buffer).unwrap();
+
+        assert_eq!(read.node_addresses, addresses);
+        assert_eq!(read.instruction_sizes, sizes);
+        assert_eq!(read.instruction_codes, codes);
+        assert_eq!(
+            read.instruction_codes[4],
+            vocab[iced_x86::Code::Push_r32] as u32
+        );
+        assert_eq!(read.labels, Some(labels));
+        assert_eq!(read.graph.edges, edges);
+        assert_eq!(read.graph.edge_types, edge_types);
+        assert_eq!(read.graph.edge_multiplicities, edge_multiplicities);
+
+        let metadata = read.metadata;
+        assert_eq!(metadata.sample_hash, sample_hash);
+        assert_eq!(metadata.vocab_hash, vocab.hash());
+        assert_eq!(metadata.graph_config, GraphConfig::default());
+        assert_eq!(metadata.superset_config, SupersetConfig::default());
+        assert_eq!(metadata.data_dependency_stats, data_dependency_stats);
+        assert_eq!(metadata.window, None);
+        assert_eq!(metadata.relation_count, 7);
+
+        // a different vocab would give different code indices
+        assert_ne!(
+            CodeVocab::new(vec![iced_x86::Code::Push_r32]).hash(),
+            metadata.vocab_hash
+        );
+    }
+
+    #[test]
+    fn test_node_features() {
+        let mut memory = MemoryImage::new();
+        #[rustfmt::skip]
+        let code = vec![
+            0xf4,       // hlt
+            0x31, 0xc0, // xor eax, eax
+            0xeb, 0x00, // jmp +0
+            0x0f,       // (truncated)
+        ];
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        memory.add_region(0x2000, Protection::READ_WRITE, vec![0x90], "".to_string());
+        let superset = memory_superset(memory, &SupersetConfig::default());
+        let vocab = CodeVocab::new(vec![]);
+
+        let export = |features: NodeFeatures| {
+            let config = GraphConfig {
+                node_features: features,
+                ..Default::default()
+            };
+            let mut buffer = std::io::Cursor::new(Vec::new());
+            superset
+                .clone()
+                .into_graph(&config)
+                .to_npz(&vocab, "", &mut buffer)
+                .unwrap();
+            buffer.set_position(0);
+            let mut npz = NpzReader::new(buffer).unwrap();
+            let names = npz.names().unwrap();
+            (npz, names)
+        };
+
+        let (_, names) = export(NodeFeatures::default());
+        assert!(!names.iter().any(|n| n.starts_with("node_bytes")));
+
+        let (mut npz, names) = export(NodeFeatures::all());
+        let mut read_u8 = |name: &str| -> Vec<u8> {
+            read_npz_array::<_, u8, Ix1>(&mut npz, &names, name)
+                .unwrap()
+                .unwrap()
+                .to_vec()
+        };
+        assert_eq!(
+            read_u8("node_bytes"),
+            [0xf4, 0x31, 0xc0, 0xeb, 0x00, 0x0f, 0x90]
+        );
+        let protections = read_u8("node_protections");
+        assert_eq!(protections[0], Protection::READ_EXECUTE.bits() as u8);
+        assert_eq!(protections[6], Protection::READ_WRITE.bits() as u8);
+        assert_eq!(read_u8("instruction_is_privileged")[..2], [1, 0]);
+        let is_invalid = read_u8("instruction_is_invalid");
+        assert_eq!((is_invalid[1], is_invalid[5]), (0, 1));
+        let falls_through = read_u8("instruction_falls_through");
+        assert_eq!((falls_through[1], falls_through[3]), (1, 0));
+        let has_jump_target = read_u8("instruction_has_jump_target");
+        assert_eq!((has_jump_target[1], has_jump_target[3]), (0, 1));
+
+        let defines = read_npz_array::<_, u64, Ix1>(&mut npz, &names, "instruction_defines")
+            .unwrap()
+            .unwrap();
+        assert_ne!(defines[1] & RegisterSet::EAX.bits(), 0);
+        assert!(
+            read_npz_array::<_, u64, Ix1>(&mut npz, &names, "instruction_uses")
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_instruction_references() {
+        #[rustfmt::skip]
+        let code = vec![
+            0xc7, 0x05, 0x08, 0x20, 0x00, 0x00, // mov dword [0x2008], 0x2000
+            0x00, 0x20, 0x00, 0x00,
+            0xa1, 0x04, 0x20, 0x00, 0x00,       // mov eax, [0x2004]
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        memory.add_region(
+            0x2000,
+            Protection::READ_WRITE,
+            vec![0; 0x10],
+            "".to_string(),
+        );
+        let config = SupersetConfig {
+            data_references: true,
+            ..Default::default()
+        };
+        let superset = memory_superset(memory, &config);
+
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        superset
+            .into_graph(&GraphConfig::default())
+            .to_npz(&CodeVocab::new(vec![]), "", &mut buffer)
+            .unwrap();
+        buffer.set_position(0);
+        let mut npz = NpzReader::new(buffer).unwrap();
+        let names = npz.names().unwrap();
+        let references = read_npz_array::<_, i64, Ix2>(&mut npz, &names, "instruction_references")
+            .unwrap()
+            .unwrap();
+        // both the immediate and the displacement are kept
+        assert_eq!(references.row(0).to_vec(), [0x2000, 0x2008]);
+        assert_eq!(references.row(10).to_vec(), [0x2004, -1]);
+        assert!(references.row(11).iter().all(|&v| v == -1));
+    }
+}
@@ -0,0 +1,160 @@
+//! Splitting a superset into address windows, so that the big samples can be made into several smaller graphs
+//!
+//! Each window takes the nodes of its core addresses and of a halo around them. The halo nodes give the
+//! core ones their context, but their predictions are taken from the window that has them in the core.
+
+use super::Index32;
+use crate::model::interval_set::Interval;
+use crate::model::SupersetSample;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Maps the nodes of a window graph back to the superset it was cut from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GraphWindow {
+    /// The addresses the window is responsible for
+    pub core: Interval<u32>,
+    /// How many bytes were taken on each side of the core
+    pub halo: u32,
+    /// Index of each node in the whole superset
+    pub node_indices: Vec<Index32>,
+    /// Whether the node is in the core
+    pub is_core: Vec<bool>,
+}
+
+/// Core intervals of `size` bytes covering the superset, skipping the ones without nodes
+pub fn plan_windows(superset: &SupersetSample, size: u32) -> Vec<Interval<u32>> {
+    assert!(size > 0, "The window size must be positive");
+
+    let mut windows = Vec::new();
+    // the addresses are sorted, so each window starts at the first node not covered yet
+    let mut rest = superset.addresses.as_slice();
+    while let Some(&start) = rest.first() {
+        let end = start.saturating_add(size);
+        windows.push(Interval::from_start_and_end(start, end));
+        rest = &rest[rest.partition_point(|&a| a < end)..];
+        if end == u32::MAX {
+            break;
+        }
+    }
+    windows
+}
+
+/// The optional columns of the superset are either empty or have a value per node
+fn optional_column<T: Clone>(column: &[T], range: Range<usize>) -> Vec<T> {
+    if column.is_empty() {
+        Vec::new()
+    } else {
+        column[range].to_vec()
+    }
+}
+
+/// Cuts the nodes of the `core` window and the `halo` bytes around it out of the superset
+pub fn cut_window(
+    superset: &SupersetSample,
+    core: Interval<u32>,
+    halo: u32,
+) -> (SupersetSample, GraphWindow) {
+    let start = core.start().saturating_sub(halo);
+    let end = core.end().saturating_add(halo);
+    let range = superset.addresses.partition_point(|&a| a < start)
+        ..superset.addresses.partition_point(|&a| a < end);
+    let in_window = |address: u32| start <= address && address < end;
+
+    let window = SupersetSample {
+        addresses: superset.addresses[range.clone()].to_vec(),
+        instructions: superset.instructions[range.clone()].to_vec(),
+        labels: superset.labels[range.clone()].to_vec(),
+        is_function_start: superset.is_function_start[range.clone()].to_vec(),
+        data_hints: superset.data_hints[range.clone()].to_vec(),
+        bytes: superset.bytes[range.clone()].to_vec(),
+        protections: superset.protections[range.clone()].to_vec(),
+        extra: optional_column(&superset.extra, range.clone()),
+        stack_effects: optional_column(&superset.stack_effects, range.clone()),
+        jump_tables: superset
+            .jump_tables
+            .iter()
+            .filter(|t| in_window(t.source))
+            .cloned()
+            .collect(),
+        references: superset
+            .references
+            .iter()
+            .filter(|r| in_window(r.source))
+            .cloned()
+            .collect(),
+        config: superset.config.clone(),
+    };
+    let mapping = GraphWindow {
+        core,
+        halo,
+        node_indices: range.clone().map(|i| i as Index32).collect(),
+        is_core: window
+            .addresses
+            .iter()
+            .map(|&a| core.start() <= a && a < core.end())
+            .collect(),
+    };
+
+    (window, mapping)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::graph::test::memory_superset;
+    use crate::model::{GraphConfig, GraphSample, SupersetConfig};
+    use memory_image::{MemoryImage, Protection};
+
+    #[test]
+    fn test_windows_cover_superset() {
+        let mut memory = MemoryImage::new();
+        memory.add_region(
+            0x1000,
+            Protection::READ_EXECUTE,
+            vec![0x90; 0x250],
+            "".to_string(),
+        );
+        memory.add_region(
+            0x8000,
+            Protection::READ_EXECUTE,
+            vec![0x90; 0x10],
+            "".to_string(),
+        );
+        let superset = memory_superset(memory, &SupersetConfig::default());
+
+        let config = GraphConfig {
+            window_size: Some(0x100),
+            window_halo: 0x20,
+            ..Default::default()
+        };
+        let graphs = GraphSample::new_windowed(superset.clone(), &config).collect::<Vec<_>>();
+        // the gap between the regions is skipped
+        assert_eq!(graphs.len(), 4);
+
+        // every node is in the core of exactly one window
+        let mut covered = vec![0; superset.addresses.len()];
+        for graph in graphs.iter() {
+            let window = graph.window.as_ref().unwrap();
+            assert_eq!(window.node_indices.len(), graph.instructions.len());
+            for (&index, &is_core) in window.node_indices.iter().zip(window.is_core.iter()) {
+                if is_core {
+                    covered[index as usize] += 1;
+                }
+            }
+            // the nodes keep their addresses in the window graphs
+            for (&index, &address) in window.node_indices.iter().zip(graph.addresses.iter()) {
+                assert_eq!(superset.addresses[index as usize], address);
+            }
+        }
+        assert!(covered.iter().all(|&c| c == 1));
+
+        // the second window has the halo on both sides
+        let window = graphs[1].window.as_ref().unwrap();
+        assert_eq!(window.core, Interval::from_start_and_end(0x1100, 0x1200));
+        assert_eq!(window.node_indices.len(), 0x140);
+        assert!(!window.is_core[0]);
+        assert!(window.is_core[0x20]);
+        assert_eq!(window.node_indices[0x20], 0x100);
+    }
+}
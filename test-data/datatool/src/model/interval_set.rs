@@ -1,14 +1,17 @@
+use itertools::Itertools;
 use serde::de::SeqAccess;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::fmt::{Debug, Formatter};
 use std::mem;
+use std::ops::Bound;
 
 /// Represents a half-interval [start, end)
 ///
 /// Invariant: start <= end
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "(V, V)", into = "(V, V)")]
 pub struct Interval<V: num::Integer + Copy> {
     start: V,
     end: V,
@@ -153,8 +156,8 @@ impl<V: num::Integer + Debug + Copy> IntervalSet<V> {
         }
 
         // calculate before doing anything
-        let contains_start = self.contains(interval.start);
-        let contains_end = self.contains(interval.end);
+        let contains_start = self.touches(interval.start);
+        let contains_end = self.touches(interval.end);
 
         // insert start point
         let start_entry = self.intervals.entry(interval.start);
@@ -218,8 +221,8 @@ impl<V: num::Integer + Debug + Copy> IntervalSet<V> {
         // this is similar to push, but we reverse some stuff
 
         // calculate before doing anything
-        let contains_start = self.contains(interval.start);
-        let contains_end = self.contains(interval.end);
+        let contains_start = self.touches(interval.start);
+        let contains_end = self.touches(interval.end);
 
         // insert end point
         let start_entry = self.intervals.entry(interval.start);
@@ -273,13 +276,93 @@ impl<V: num::Integer + Debug + Copy> IntervalSet<V> {
         // self.check_iter();
     }
 
+    /// Whether the value is inside of an interval or is the end of one: (start, end]
+    ///
+    /// This is what decides whether a pushed (or removed) point merges with (or splits) an existing interval
+    fn touches(&self, value: V) -> bool {
+        matches!(
+            self.intervals.range(..value).next_back(),
+            Some((_, SetNode::Start))
+        )
+    }
+
     pub fn contains(&self, value: V) -> bool {
         matches!(
-            self.intervals.range(..value).last(),
+            self.intervals.range(..=value).next_back(),
             Some((_, SetNode::Start))
         )
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Total length of all the intervals
+    pub fn total_len(&self) -> V {
+        self.iter().fold(V::zero(), |acc, v| acc + v.len())
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut result = self.clone();
+        result.extend(other.iter());
+        result
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        // both sets are sorted and do not have overlaps inside, so we can just merge them
+        let mut result = Self::new();
+        let mut a = self.iter().peekable();
+        let mut b = other.iter().peekable();
+        while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+            result.push(x.intersection(*y));
+            if x.end < y.end {
+                a.next();
+            } else {
+                b.next();
+            }
+        }
+        result
+    }
+
+    pub fn difference(&self, other: &Self) -> Self {
+        let mut result = self.clone();
+        for interval in other.iter() {
+            result.remove(interval);
+        }
+        result
+    }
+
+    /// Everything inside of the `bounds` that is not in the set
+    pub fn complement(&self, bounds: Interval<V>) -> Self {
+        let mut result = Self::new();
+        result.push(bounds);
+        for interval in self.overlapping(bounds) {
+            result.remove(interval);
+        }
+        result
+    }
+
+    /// Iterates over the intervals that intersect with the `range` (without clipping them)
+    pub fn overlapping(&self, range: Interval<V>) -> impl Iterator<Item = Interval<V>> + '_ {
+        // start either from the interval containing the range start or right after it
+        let first = match self.intervals.range(..=range.start).next_back() {
+            Some((&k, SetNode::Start)) => Bound::Included(k),
+            _ => Bound::Excluded(range.start),
+        };
+
+        IntervalSetIter {
+            inner: self.intervals.range((first, Bound::Unbounded)),
+        }
+        .take_while(move |v| v.start < range.end && !range.is_empty())
+    }
+
+    /// Iterates over the gaps between the intervals
+    pub fn gaps(&self) -> impl Iterator<Item = Interval<V>> + '_ {
+        self.iter()
+            .tuple_windows()
+            .map(|(a, b)| Interval::from_start_and_end(a.end, b.start))
+    }
+
     /// Shifts all intervals by the given offset.
     pub fn shift(&mut self, offset: V) {
         let old_intervals = mem::take(&mut self.intervals);
@@ -297,7 +380,7 @@ impl<V: num::Integer + Debug + Copy> IntervalSet<V> {
 
     pub fn iter(&self) -> IntervalSetIter<'_, V> {
         IntervalSetIter {
-            inner: self.intervals.iter(),
+            inner: self.intervals.range(..),
         }
     }
 
@@ -309,7 +392,15 @@ impl<V: num::Integer + Debug + Copy> IntervalSet<V> {
 }
 
 pub struct IntervalSetIter<'a, V: num::Integer + Copy> {
-    inner: std::collections::btree_map::Iter<'a, V, SetNode>,
+    inner: std::collections::btree_map::Range<'a, V, SetNode>,
+}
+
+impl<V: num::Integer + Debug + Copy> FromIterator<Interval<V>> for IntervalSet<V> {
+    fn from_iter<T: IntoIterator<Item = Interval<V>>>(iter: T) -> Self {
+        let mut result = Self::new();
+        result.extend(iter);
+        result
+    }
 }
 
 impl<'a, V: num::Integer + Copy> Iterator for IntervalSetIter<'a, V> {
@@ -338,6 +429,171 @@ impl<'a, V: num::Integer + Copy> Iterator for IntervalSetIter<'a, V> {
     }
 }
 
+/// A map from non-overlapping intervals to values
+///
+/// Inserting an interval overwrites whatever was there before, splitting the partially covered intervals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntervalMap<V: num::Integer + Copy, T> {
+    // start -> (end, value)
+    intervals: BTreeMap<V, (V, T)>,
+}
+
+impl<V: num::Integer + Debug + Copy + Serialize, T: Serialize> Serialize for IntervalMap<V, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeSeq;
+        let mut seq = serializer.serialize_seq(Some(self.intervals.len()))?;
+        for (start, (end, value)) in self.intervals.iter() {
+            seq.serialize_element(&(start, end, value))?;
+        }
+        seq.end()
+    }
+}
+
+struct IntervalMapVisitor<'de, V, T> {
+    phantom: std::marker::PhantomData<&'de (V, T)>,
+}
+
+impl<'de, V: num::Integer + Debug + Copy + Deserialize<'de>, T: Clone + Deserialize<'de>>
+    serde::de::Visitor<'de> for IntervalMapVisitor<'de, V, T>
+{
+    type Value = IntervalMap<V, T>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a sequence of intervals with values")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut res = IntervalMap::new();
+
+        while let Some((start, end, value)) = seq.next_element::<(V, V, T)>()? {
+            if end < start {
+                return Err(serde::de::Error::custom("interval end is before its start"));
+            }
+            res.insert(Interval::from_start_and_end(start, end), value);
+        }
+
+        Ok(res)
+    }
+}
+
+impl<
+        'de,
+        V: num::Integer + Debug + Copy + Deserialize<'de> + 'de,
+        T: Clone + Deserialize<'de> + 'de,
+    > Deserialize<'de> for IntervalMap<V, T>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(IntervalMapVisitor {
+            phantom: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<V: num::Integer + Debug + Copy, T: Clone> IntervalMap<V, T> {
+    pub fn new() -> Self {
+        Self {
+            intervals: BTreeMap::new(),
+        }
+    }
+
+    /// Maps the interval to the value, overwriting the previous values in it
+    pub fn insert(&mut self, interval: Interval<V>, value: T) {
+        if interval.is_empty() {
+            return;
+        }
+
+        // an interval starting before the new one may stick out on either side of it
+        if let Some((&start, (end, old_value))) = self.intervals.range(..interval.start).next_back()
+        {
+            let end = *end;
+            if end > interval.start {
+                let old_value = old_value.clone();
+                self.intervals
+                    .insert(start, (interval.start, old_value.clone()));
+                if end > interval.end {
+                    self.intervals.insert(interval.end, (end, old_value));
+                }
+            }
+        }
+
+        // the intervals starting inside of the new one are either covered or stick out on the right
+        let inner = self
+            .intervals
+            .range(interval.start..interval.end)
+            .map(|(&k, _)| k)
+            .collect::<smallvec::SmallVec<[V; 8]>>();
+        for k in inner {
+            let (end, old_value) = self.intervals.remove(&k).unwrap();
+            if end > interval.end {
+                self.intervals.insert(interval.end, (end, old_value));
+            }
+        }
+
+        self.intervals.insert(interval.start, (interval.end, value));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Finds the interval containing the point
+    pub fn get_entry(&self, point: V) -> Option<(Interval<V>, &T)> {
+        match self.intervals.range(..=point).next_back() {
+            Some((&start, (end, value))) if *end > point => {
+                Some((Interval::from_start_and_end(start, *end), value))
+            }
+            _ => None,
+        }
+    }
+
+    /// Iterates over the intervals that intersect with the `range` (without clipping them)
+    pub fn overlapping(&self, range: Interval<V>) -> impl Iterator<Item = (Interval<V>, &T)> + '_ {
+        let first = match self.get_entry(range.start) {
+            Some((interval, _)) => interval.start,
+            None => range.start,
+        };
+
+        self.intervals
+            .range(first..)
+            .map(|(&start, (end, value))| (Interval::from_start_and_end(start, *end), value))
+            .take_while(move |(v, _)| v.start < range.end && !range.is_empty())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Interval<V>, &T)> + '_ {
+        self.intervals
+            .iter()
+            .map(|(&start, (end, value))| (Interval::from_start_and_end(start, *end), value))
+    }
+
+    /// Shifts all intervals by the given offset.
+    pub fn shift(&mut self, offset: V) {
+        let old_intervals = mem::take(&mut self.intervals);
+        for (k, (end, v)) in old_intervals {
+            self.intervals.insert(k + offset, (end + offset, v));
+        }
+    }
+
+    /// Keeps only the parts of the intervals inside of the `range`
+    pub fn clip(&mut self, range: Interval<V>) {
+        let old_intervals = mem::take(&mut self.intervals);
+        for (start, (end, value)) in old_intervals {
+            let interval = Interval::from_start_and_end(start, end).intersection(range);
+            if !interval.is_empty() {
+                self.intervals.insert(interval.start, (interval.end, value));
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     #[test]
@@ -438,6 +694,18 @@ mod test {
         );
     }
 
+    #[test]
+    pub fn test_contains() {
+        use super::{Interval, IntervalSet};
+
+        let mut set = IntervalSet::<u32>::new();
+        set.push(Interval::from_start_and_end(1, 3));
+        assert!(!set.contains(0));
+        assert!(set.contains(1));
+        assert!(set.contains(2));
+        assert!(!set.contains(3));
+    }
+
     #[test]
     pub fn test_interval_set_serde() {
         use super::{Interval, IntervalSet};
@@ -459,4 +727,192 @@ mod test {
         let set2: IntervalSet<u32> = serde_json::from_str(&json).unwrap();
         assert_eq!(set, set2);
     }
+
+    #[test]
+    pub fn test_interval_map() {
+        use super::{Interval, IntervalMap};
+
+        let mut map = IntervalMap::<u32, &str>::new();
+        map.insert(Interval::from_start_and_end(0, 10), "a");
+        map.insert(Interval::from_start_and_end(20, 30), "b");
+        // splits "a" and covers the beginning of "b"
+        map.insert(Interval::from_start_and_end(5, 25), "c");
+        // in the middle of "c"
+        map.insert(Interval::from_start_and_end(10, 12), "d");
+
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            vec![
+                (Interval::from_start_and_end(0, 5), &"a"),
+                (Interval::from_start_and_end(5, 10), &"c"),
+                (Interval::from_start_and_end(10, 12), &"d"),
+                (Interval::from_start_and_end(12, 25), &"c"),
+                (Interval::from_start_and_end(25, 30), &"b"),
+            ]
+        );
+
+        assert_eq!(map.get_entry(4).map(|(_, v)| *v), Some("a"));
+        assert_eq!(map.get_entry(5).map(|(_, v)| *v), Some("c"));
+        assert_eq!(
+            map.get_entry(29),
+            Some((Interval::from_start_and_end(25, 30), &"b"))
+        );
+        assert_eq!(map.get_entry(30), None);
+
+        assert_eq!(
+            map.overlapping(Interval::from_start_and_end(11, 26))
+                .map(|(_, v)| *v)
+                .collect::<Vec<_>>(),
+            vec!["d", "c", "b"]
+        );
+
+        let json = serde_json::to_string(&map).unwrap();
+        let map2: IntervalMap<u32, &str> = serde_json::from_str(&json).unwrap();
+        assert_eq!(map, map2);
+    }
+
+    mod prop {
+        use super::super::{Interval, IntervalMap, IntervalSet};
+        use proptest::prelude::*;
+
+        const UNIVERSE: u32 = 64;
+
+        /// The naive model: one bool per point
+        type Bitmap = [bool; UNIVERSE as usize];
+
+        fn to_bitmap(set: &IntervalSet<u32>) -> Bitmap {
+            let mut result = [false; UNIVERSE as usize];
+            for interval in set.iter() {
+                for i in interval.start()..interval.end() {
+                    result[i as usize] = true;
+                }
+            }
+            result
+        }
+
+        fn interval() -> impl Strategy<Value = Interval<u32>> {
+            (0..UNIVERSE, 0..UNIVERSE / 4).prop_map(|(start, len)| {
+                Interval::from_start_and_len(start, len.min(UNIVERSE - start))
+            })
+        }
+
+        /// A set built by a random sequence of pushes and removes, along with its bitmap model
+        fn interval_set() -> impl Strategy<Value = (IntervalSet<u32>, Bitmap)> {
+            prop::collection::vec((any::<bool>(), interval()), 0..12).prop_map(|ops| {
+                let mut set = IntervalSet::new();
+                let mut bitmap = [false; UNIVERSE as usize];
+                for (is_push, interval) in ops {
+                    if is_push {
+                        set.push(interval);
+                    } else {
+                        set.remove(interval);
+                    }
+                    for i in interval.start()..interval.end() {
+                        bitmap[i as usize] = is_push;
+                    }
+                }
+                (set, bitmap)
+            })
+        }
+
+        fn bitmap_op(a: &Bitmap, b: &Bitmap, op: impl Fn(bool, bool) -> bool) -> Bitmap {
+            let mut result = [false; UNIVERSE as usize];
+            for i in 0..UNIVERSE as usize {
+                result[i] = op(a[i], b[i]);
+            }
+            result
+        }
+
+        /// Checks that the intervals are sorted, non-empty and are not touching each other
+        fn assert_canonical(set: &IntervalSet<u32>) {
+            for interval in set.iter() {
+                assert!(!interval.is_empty());
+            }
+            for (a, b) in set.iter().zip(set.iter().skip(1)) {
+                assert!(a.end() < b.start());
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn push_and_remove((set, bitmap) in interval_set()) {
+                assert_canonical(&set);
+                prop_assert_eq!(to_bitmap(&set), bitmap);
+                for i in 0..UNIVERSE {
+                    prop_assert_eq!(set.contains(i), bitmap[i as usize]);
+                }
+                prop_assert_eq!(
+                    set.total_len(),
+                    bitmap.iter().filter(|&&v| v).count() as u32
+                );
+            }
+
+            #[test]
+            fn set_algebra((a, a_bitmap) in interval_set(), (b, b_bitmap) in interval_set()) {
+                let union = a.union(&b);
+                assert_canonical(&union);
+                prop_assert_eq!(to_bitmap(&union), bitmap_op(&a_bitmap, &b_bitmap, |x, y| x || y));
+
+                let intersection = a.intersection(&b);
+                assert_canonical(&intersection);
+                prop_assert_eq!(
+                    to_bitmap(&intersection),
+                    bitmap_op(&a_bitmap, &b_bitmap, |x, y| x && y)
+                );
+
+                let difference = a.difference(&b);
+                assert_canonical(&difference);
+                prop_assert_eq!(
+                    to_bitmap(&difference),
+                    bitmap_op(&a_bitmap, &b_bitmap, |x, y| x && !y)
+                );
+            }
+
+            #[test]
+            fn range_queries((set, bitmap) in interval_set(), range in interval()) {
+                let complement = set.complement(range);
+                assert_canonical(&complement);
+                let mut expected = [false; UNIVERSE as usize];
+                for i in range.start()..range.end() {
+                    expected[i as usize] = !bitmap[i as usize];
+                }
+                prop_assert_eq!(to_bitmap(&complement), expected);
+
+                let overlapping = set.overlapping(range).collect::<Vec<_>>();
+                let expected = set
+                    .iter()
+                    .filter(|v| !v.intersection(range).is_empty())
+                    .collect::<Vec<_>>();
+                prop_assert_eq!(overlapping, expected);
+
+                let gaps = set.gaps().collect::<IntervalSet<_>>();
+                let expected = match (set.iter().next(), set.iter().last()) {
+                    (Some(first), Some(last)) => {
+                        set.complement(Interval::from_start_and_end(first.start(), last.end()))
+                    }
+                    _ => IntervalSet::new(),
+                };
+                prop_assert_eq!(gaps, expected);
+            }
+
+            #[test]
+            fn map_insert(ops in prop::collection::vec(interval(), 0..12)) {
+                let mut map = IntervalMap::new();
+                let mut model = [None; UNIVERSE as usize];
+                for (i, interval) in ops.into_iter().enumerate() {
+                    map.insert(interval, i);
+                    for p in interval.start()..interval.end() {
+                        model[p as usize] = Some(i);
+                    }
+                }
+
+                for p in 0..UNIVERSE {
+                    prop_assert_eq!(map.get_entry(p).map(|(_, v)| *v), model[p as usize]);
+                }
+                for ((a, _), (b, _)) in map.iter().zip(map.iter().skip(1)) {
+                    prop_assert!(a.end() <= b.start());
+                }
+            }
+        }
+    }
 }
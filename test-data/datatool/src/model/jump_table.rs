@@ -0,0 +1,364 @@
+//! Resolution of the indirect jumps and calls going through tables of code pointers
+//!
+//! Only the idioms emitted by the common compilers are recognized:
+//! - `jmp [idx*4+table]` or `call [idx*4+table]` (and a single pointer, without the index)
+//! - `mov reg, [idx*4+table]; jmp reg`
+//! - `add reg, [ebx+idx*4+table@GOTOFF]; jmp reg` and `mov reg, [ebx+idx*4+table@GOTOFF]; add reg, ebx; jmp reg`
+//!   in the 32-bit PIC code, where `ebx` is assumed to point to the GOT
+//! - `lea base, [rip+table]; movsxd reg, [base+idx*4]; add reg, base; jmp reg` in the 64-bit code
+//!
+//! The superset has no notion of the "previous instruction", so the instructions leading to the jump
+//! are found by decoding at every address that could fall through into it.
+
+use crate::model::interval_set::Interval;
+use crate::model::ExecutableSample;
+use iced_x86::{DecoderOptions, FlowControl, Mnemonic, OpKind, Register};
+use memory_image::MemoryImage;
+use serde::{Deserialize, Serialize};
+
+/// The most entries read from a single table
+const MAX_ENTRIES: usize = 1024;
+/// The longest possible x86 instruction
+const MAX_INSTRUCTION_LEN: u32 = 15;
+
+/// A table of code pointers used by an indirect jump or call
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JumpTable {
+    /// Address of the jump (or call) instruction
+    pub source: u32,
+    /// Bytes of the table itself
+    pub table: Interval<u32>,
+    /// The resolved entries, in the table order (there can be duplicates)
+    pub targets: Vec<u32>,
+}
+
+/// How the table entries are turned into the addresses
+#[derive(Copy, Clone, Debug)]
+enum Entries {
+    /// Pointers of the native size
+    Absolute,
+    /// Signed 32-bit offsets from the given base
+    Relative(u64),
+}
+
+/// Start of the GOT, which is what `ebx` points to in the 32-bit PIC code
+pub fn global_offset_table(sample: &ExecutableSample) -> Option<u32> {
+    [".got.plt", ".got"].into_iter().find_map(|name| {
+        sample
+            .sections
+            .iter()
+            .find(|(_, section)| section.as_str() == name)
+            .map(|(interval, _)| interval.start())
+    })
+}
+
+/// Tries to find the table used by the indirect branch `instruction`
+pub fn resolve_jump_table(
+    instruction: &iced_x86::Instruction,
+    memory: &MemoryImage,
+    bitness: u32,
+    got: Option<u32>,
+) -> Option<JumpTable> {
+    if bitness == 16
+        || !matches!(
+            instruction.flow_control(),
+            FlowControl::IndirectBranch | FlowControl::IndirectCall
+        )
+    {
+        return None;
+    }
+    let resolver = Resolver {
+        memory,
+        bitness,
+        got,
+    };
+
+    let (table, targets) = match instruction.op0_kind() {
+        OpKind::Memory => resolver.read_table(
+            resolver.table_address(instruction, (bitness / 8) as usize)?,
+            Entries::Absolute,
+        )?,
+        OpKind::Register if instruction.mnemonic() == Mnemonic::Jmp => {
+            resolver.register_target(instruction.ip32(), instruction.op0_register())?
+        }
+        _ => return None,
+    };
+
+    Some(JumpTable {
+        source: instruction.ip32(),
+        table,
+        targets,
+    })
+}
+
+struct Resolver<'a> {
+    memory: &'a MemoryImage,
+    bitness: u32,
+    got: Option<u32>,
+}
+
+impl<'a> Resolver<'a> {
+    /// The instructions that end right at `address` and fall through into it
+    fn predecessors(&self, address: u32) -> impl Iterator<Item = iced_x86::Instruction> + 'a {
+        let memory = self.memory;
+        let bitness = self.bitness;
+        (1..=MAX_INSTRUCTION_LEN).filter_map(move |len| {
+            let start = address.checked_sub(len)?;
+            let bytes = memory.execute_all_at(start);
+            let mut decoder = iced_x86::Decoder::with_ip(
+                bitness,
+                &bytes[..bytes.len().min(len as usize)],
+                start as u64,
+                DecoderOptions::NONE,
+            );
+            let instruction = decoder.decode();
+            (!instruction.is_invalid()
+                && instruction.len() == len as usize
+                && instruction.flow_control() == FlowControl::Next)
+                .then_some(instruction)
+        })
+    }
+
+    /// Resolves `jmp reg` at `address` by looking at the instructions computing `reg`
+    fn register_target(&self, address: u32, reg: Register) -> Option<(Interval<u32>, Vec<u32>)> {
+        let writes = |instruction: &iced_x86::Instruction, mnemonic: Mnemonic| {
+            instruction.mnemonic() == mnemonic
+                && instruction.op0_kind() == OpKind::Register
+                && instruction.op0_register() == reg
+        };
+
+        self.predecessors(address).find_map(|load| {
+            if writes(&load, Mnemonic::Mov) && load.op1_kind() == OpKind::Memory {
+                // mov reg, [idx*4+table]
+                if load.memory_base() != Register::None {
+                    return None;
+                }
+                let table = self.table_address(&load, (self.bitness / 8) as usize)?;
+                self.read_table(table, Entries::Absolute)
+            } else if writes(&load, Mnemonic::Add) && load.op1_kind() == OpKind::Memory {
+                // add reg, [ebx+idx*4+table@GOTOFF]
+                let got = self.got.filter(|_| load.memory_base() == Register::EBX)?;
+                let table = self.table_address(&load, 4)?;
+                self.read_table(table, Entries::Relative(got as u64))
+            } else if writes(&load, Mnemonic::Add) && load.op1_kind() == OpKind::Register {
+                // mov reg, [base+idx*4+...]; add reg, base
+                let base = load.op1_register();
+                self.predecessors(load.ip32()).find_map(|load| {
+                    let is_load = (writes(&load, Mnemonic::Mov) || writes(&load, Mnemonic::Movsxd))
+                        && load.op1_kind() == OpKind::Memory
+                        && load.memory_base() == base;
+                    if !is_load {
+                        return None;
+                    }
+                    let base = self.register_value(load.ip32(), base)?;
+                    let table = self.table_address_from(&load, base, 4)?;
+                    self.read_table(table, Entries::Relative(base))
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Value of `reg` right before the instruction at `address`, if it's known
+    fn register_value(&self, address: u32, reg: Register) -> Option<u64> {
+        if self.bitness == 32 && reg == Register::EBX {
+            return self.got.map(|v| v as u64);
+        }
+        self.predecessors(address).find_map(|instruction| {
+            (instruction.mnemonic() == Mnemonic::Lea
+                && instruction.op0_register() == reg
+                && instruction.is_ip_rel_memory_operand())
+            .then(|| instruction.ip_rel_memory_address())
+        })
+    }
+
+    /// Address of the table accessed by the memory operand of `instruction`
+    fn table_address(&self, instruction: &iced_x86::Instruction, entry_size: usize) -> Option<u32> {
+        let base = match instruction.memory_base() {
+            Register::None => 0,
+            Register::EIP | Register::RIP => {
+                return self.table_address_from(instruction, 0, entry_size)
+            }
+            Register::EBX if self.bitness == 32 => self.got? as u64,
+            _ => return None,
+        };
+        self.table_address_from(instruction, base, entry_size)
+    }
+
+    fn table_address_from(
+        &self,
+        instruction: &iced_x86::Instruction,
+        base: u64,
+        entry_size: usize,
+    ) -> Option<u32> {
+        // a single pointer is fine, otherwise the index has to step over the entries
+        if instruction.memory_index() != Register::None
+            && instruction.memory_index_scale() as usize != entry_size
+        {
+            return None;
+        }
+        let address = if instruction.is_ip_rel_memory_operand() {
+            instruction.ip_rel_memory_address()
+        } else {
+            base.wrapping_add(instruction.memory_displacement64())
+        };
+        if self.bitness == 64 {
+            u32::try_from(address).ok()
+        } else {
+            // the 32-bit address arithmetic wraps around
+            Some(address as u32)
+        }
+    }
+
+    /// Reads the entries until one of them doesn't point to the executable memory
+    fn read_table(&self, table: u32, entries: Entries) -> Option<(Interval<u32>, Vec<u32>)> {
+        let entry_size = match entries {
+            Entries::Absolute => (self.bitness / 8) as usize,
+            Entries::Relative(_) => 4,
+        };
+
+        let targets = self
+            .memory
+            .read_all_at(table)
+            .chunks_exact(entry_size)
+            .take(MAX_ENTRIES)
+            .map_while(|entry| {
+                let target = match (entries, entry_size) {
+                    (Entries::Absolute, 4) => u32::from_le_bytes(entry.try_into().unwrap()) as u64,
+                    (Entries::Absolute, _) => u64::from_le_bytes(entry.try_into().unwrap()),
+                    (Entries::Relative(base), _) => base
+                        .wrapping_add(i32::from_le_bytes(entry.try_into().unwrap()) as i64 as u64),
+                };
+                let target = if self.bitness == 64 {
+                    u32::try_from(target).ok()?
+                } else {
+                    target as u32
+                };
+                (!self.memory.execute_all_at(target).is_empty()).then_some(target)
+            })
+            .collect::<Vec<_>>();
+
+        if targets.is_empty() {
+            return None;
+        }
+        let len = (targets.len() * entry_size) as u32;
+        Some((Interval::from_start_and_end(table, table + len), targets))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use memory_image::Protection;
+
+    fn resolve(
+        memory: &MemoryImage,
+        bitness: u32,
+        got: Option<u32>,
+        jump: u32,
+    ) -> Option<JumpTable> {
+        let mut decoder = iced_x86::Decoder::with_ip(
+            bitness,
+            memory.execute_all_at(jump),
+            jump as u64,
+            DecoderOptions::NONE,
+        );
+        resolve_jump_table(&decoder.decode(), memory, bitness, got)
+    }
+
+    fn table(entries: &[u32]) -> Vec<u8> {
+        entries.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_absolute() {
+        #[rustfmt::skip]
+        let code = vec![
+            0xff, 0x24, 0x85, 0x00, 0x20, 0x00, 0x00, // jmp [eax*4+0x2000]
+            0x8b, 0x04, 0x85, 0x00, 0x20, 0x00, 0x00, // mov eax, [eax*4+0x2000]
+            0xff, 0xe0,                               // jmp eax
+            0xff, 0x15, 0x0c, 0x20, 0x00, 0x00,       // call [0x200c]
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        // the last entry is not code, so the table ends before it
+        let entries = table(&[0x1000, 0x1007, 0x1000, 0x1010, 0x2000]);
+        memory.add_region(0x2000, Protection::READ, entries, "".to_string());
+
+        let expected = JumpTable {
+            source: 0x1000,
+            table: Interval::from_start_and_end(0x2000, 0x2010),
+            targets: vec![0x1000, 0x1007, 0x1000, 0x1010],
+        };
+        assert_eq!(resolve(&memory, 32, None, 0x1000), Some(expected.clone()));
+        assert_eq!(
+            resolve(&memory, 32, None, 0x100e),
+            Some(JumpTable {
+                source: 0x100e,
+                ..expected
+            })
+        );
+
+        let pointer = resolve(&memory, 32, None, 0x1010).unwrap();
+        assert_eq!(pointer.table, Interval::from_start_and_end(0x200c, 0x2010));
+        assert_eq!(pointer.targets, vec![0x1010]);
+    }
+
+    #[test]
+    fn test_pic() {
+        #[rustfmt::skip]
+        let code = vec![
+            0x03, 0x84, 0x83, 0x00, 0xf0, 0xff, 0xff, // add eax, [ebx+eax*4-0x1000]
+            0xff, 0xe0,                               // jmp eax
+            0x8b, 0x8c, 0x83, 0x00, 0xf0, 0xff, 0xff, // mov ecx, [ebx+eax*4-0x1000]
+            0x01, 0xd9,                               // add ecx, ebx
+            0xff, 0xe1,                               // jmp ecx
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        // the entries are relative to the GOT at 0x3000
+        let entries = table(&[(-0x2000i32) as u32, (-0x1ff7i32) as u32]);
+        memory.add_region(0x2000, Protection::READ, entries, "".to_string());
+
+        let expected = |source| JumpTable {
+            source,
+            table: Interval::from_start_and_end(0x2000, 0x2008),
+            targets: vec![0x1000, 0x1009],
+        };
+        assert_eq!(
+            resolve(&memory, 32, Some(0x3000), 0x1007),
+            Some(expected(0x1007))
+        );
+        assert_eq!(
+            resolve(&memory, 32, Some(0x3000), 0x1012),
+            Some(expected(0x1012))
+        );
+        // without knowing the GOT nothing can be done
+        assert_eq!(resolve(&memory, 32, None, 0x1007), None);
+    }
+
+    #[test]
+    fn test_relative_64() {
+        #[rustfmt::skip]
+        let code = vec![
+            0x48, 0x8d, 0x15, 0xf9, 0x0f, 0x00, 0x00, // lea rdx, [rip+0xff9] (0x2000)
+            0x48, 0x63, 0x04, 0x82,                   // movsxd rax, [rdx+rax*4]
+            0x48, 0x01, 0xd0,                         // add rax, rdx
+            0xff, 0xe0,                               // jmp rax
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        let entries = table(&[(-0x1000i32) as u32, (-0xff2i32) as u32]);
+        memory.add_region(0x2000, Protection::READ, entries, "".to_string());
+
+        assert_eq!(
+            resolve(&memory, 64, None, 0x100e),
+            Some(JumpTable {
+                source: 0x100e,
+                table: Interval::from_start_and_end(0x2000, 0x2008),
+                targets: vec![0x1000, 0x100e],
+            })
+        );
+    }
+}
@@ -1,24 +1,39 @@
+mod architecture;
+pub mod archive;
 mod graph;
 pub mod interval_set;
+mod jump_table;
+mod references;
+pub mod sample_format;
+mod source;
+mod stack_slots;
 mod superset;
+pub mod validate;
 mod vocab;
 
-pub use graph::GraphSample;
-pub use superset::{InstructionFeature, Label, SupersetSample};
+pub use architecture::Architecture;
+pub use graph::{AddressIndex, DataDependencyStats, GraphConfig, GraphSample, RelationType};
+pub use jump_table::JumpTable;
+pub use references::Reference;
+pub use source::{SampleSource, SourceKind};
+pub use superset::{InstructionFeature, Label, SupersetConfig, SupersetSample};
 pub use vocab::{CodeVocab, CodeVocabBuilder};
 
 use crate::loader::dump_elf_symbols;
-use crate::loader::load_executable;
+use crate::loader::{load_executable, load_sections};
 use crate::{dump_pdb, Interval};
 use anyhow::{bail, Context, Result};
-use interval_set::IntervalSet;
+use interval_set::{IntervalMap, IntervalSet};
 use memory_image::{MemoryImage, Protection};
-use object::read::elf::ElfFile32;
+use object::elf;
+use object::read::elf::{ElfFile, FileHeader};
 use object::read::pe::PeFile32;
 use object::write::elf::ProgramHeader;
 use object::Endianness;
 use pdb::PDB;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
 use std::io::Write;
 use uuid::Uuid;
 
@@ -26,6 +41,10 @@ use uuid::Uuid;
 pub struct AddressClasses {
     pub true_instructions: IntervalSet<u32>,
     pub true_data: IntervalSet<u32>,
+    /// Entry points of the functions (and thunks)
+    pub function_starts: BTreeSet<u32>,
+    /// Names of the functions and data objects, as found in the debug info
+    pub symbols: IntervalMap<u32, String>,
 }
 
 impl AddressClasses {
@@ -33,41 +52,54 @@ impl AddressClasses {
         Self {
             true_instructions: IntervalSet::new(),
             true_data: IntervalSet::new(),
+            function_starts: BTreeSet::new(),
+            symbols: IntervalMap::new(),
         }
     }
     pub fn relocate(&mut self, offset: u32) {
         self.true_instructions.shift(offset);
         self.true_data.shift(offset);
+        self.function_starts = self.function_starts.iter().map(|&v| v + offset).collect();
+        self.symbols.shift(offset);
     }
 
     pub fn filter_to(&mut self, range: Interval<u32>) {
-        let mut true_instructions = IntervalSet::new();
-        true_instructions.extend(
-            self.true_instructions
-                .iter()
-                .map(|v| v.intersection(range))
-                .filter(|v| !v.is_empty()),
-        );
-        let mut true_data = IntervalSet::new();
-        true_data.extend(
-            self.true_data
-                .iter()
-                .map(|v| v.intersection(range))
-                .filter(|v| !v.is_empty()),
-        );
+        self.true_instructions = self
+            .true_instructions
+            .overlapping(range)
+            .map(|v| v.intersection(range))
+            .collect();
+        self.true_data = self
+            .true_data
+            .overlapping(range)
+            .map(|v| v.intersection(range))
+            .collect();
+        self.function_starts
+            .retain(|&v| range.start() <= v && v < range.end());
+        self.symbols.clip(range);
+    }
 
-        self.true_instructions = true_instructions;
-        self.true_data = true_data;
+    /// Describes where the address is in terms of the ground truth, e.g. "data in `memcpy`+0x1c"
+    pub fn describe(&self, address: u32) -> String {
+        let kind = if self.true_instructions.contains(address) {
+            "code"
+        } else if self.true_data.contains(address) {
+            "data"
+        } else {
+            "unlabeled"
+        };
+
+        match self.symbols.get_entry(address) {
+            Some((interval, name)) => {
+                format!("{} in `{}`+0x{:x}", kind, name, address - interval.start())
+            }
+            None => kind.to_string(),
+        }
     }
 
+    /// Number of bytes covered by either code or data
     pub fn coverage(&self) -> u32 {
-        // TODO: handle interval overlaps
-        // ideally we should not allow them
-        self.true_instructions
-            .iter()
-            .map(|v| v.len())
-            .chain(self.true_data.iter().map(|v| v.len()))
-            .sum()
+        self.true_instructions.union(&self.true_data).total_len()
     }
 
     pub fn dump(&self) -> String {
@@ -98,7 +130,7 @@ impl AddressClasses {
                 }
             }
 
-            writeln!(
+            write!(
                 result,
                 "0x{:08x} - 0x{:08x} (0x{:04x}) {}",
                 interval.start(),
@@ -107,6 +139,15 @@ impl AddressClasses {
                 kind
             )
             .unwrap();
+            let mut names = self.symbols.overlapping(interval).map(|(_, name)| name);
+            if let Some(name) = names.next() {
+                write!(result, " {}", name).unwrap();
+                let more = names.count();
+                if more > 0 {
+                    write!(result, " (+{} more)", more).unwrap();
+                }
+            }
+            writeln!(result).unwrap();
             prev = Some(interval.end());
         }
 
@@ -118,18 +159,55 @@ impl AddressClasses {
 pub struct ExecutableSample {
     pub memory: MemoryImage,
     pub classes: AddressClasses,
+    pub source: Option<SampleSource>,
+    pub architecture: Architecture,
+    /// Names of the sections of the original executable
+    pub sections: IntervalMap<u32, String>,
 }
 
 impl ExecutableSample {
     pub fn new(memory: MemoryImage, classes: AddressClasses) -> Result<Self> {
-        Ok(ExecutableSample { memory, classes })
+        Ok(ExecutableSample {
+            memory,
+            classes,
+            source: None,
+            architecture: Architecture::X86_32,
+            sections: IntervalMap::new(),
+        })
+    }
+
+    pub fn with_sections(mut self, sections: IntervalMap<u32, String>) -> Self {
+        self.sections = sections;
+        self
+    }
+
+    pub fn with_architecture(mut self, architecture: Architecture) -> Self {
+        self.architecture = architecture;
+        self
+    }
+
+    pub fn with_source(mut self, source: SampleSource) -> Self {
+        self.source = Some(source);
+        self
     }
 
-    pub fn from_elf(executable: &ElfFile32, debug_info: Option<&ElfFile32>) -> Result<Self> {
+    pub fn from_elf<Elf: FileHeader>(
+        executable: &ElfFile<Elf>,
+        debug_info: Option<&ElfFile<Elf>>,
+    ) -> Result<Self> {
+        let architecture = match executable.raw_header().e_machine(executable.endian()) {
+            elf::EM_386 => Architecture::X86_32,
+            elf::EM_X86_64 => Architecture::X86_64,
+            machine => bail!("Unsupported ELF machine: {}", machine),
+        };
+
         let memory = load_executable(executable)?;
+        let sections = load_sections(executable)?;
         let classes = dump_elf_symbols(&memory, debug_info.unwrap_or(executable))?;
 
-        Self::new(memory, classes)
+        Ok(Self::new(memory, classes)?
+            .with_architecture(architecture)
+            .with_sections(sections))
     }
 
     pub fn from_pe_and_pdb<'s, S: std::io::Read + std::io::Seek + std::fmt::Debug + 's>(
@@ -153,12 +231,13 @@ impl ExecutableSample {
         };
 
         let memory = load_executable(executable)?;
+        let sections = load_sections(executable)?;
         let classes = dump_pdb(
             executable.relative_address_base().try_into().unwrap(),
             debug_info,
         )?;
 
-        Self::new(memory, classes)
+        Ok(Self::new(memory, classes)?.with_sections(sections))
     }
 
     pub fn size(&self) -> u64 {
@@ -185,27 +264,37 @@ impl ExecutableSample {
         covered as f64 / total as f64
     }
 
+    /// Hex SHA-256 of the sample contents, identifies the sample the exported data was made from
+    ///
+    /// Only the memory, the classes and the architecture are hashed, so re-fetching the same
+    /// executable gives the same hash. It's computed over the in-memory representation, so it
+    /// doesn't change with the `.sample` file format version or the compression either
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        bincode::serialize_into(
+            &mut hasher,
+            &(&self.memory, &self.classes, &self.architecture),
+        )
+        .expect("Hashing can't fail");
+        hex::encode(hasher.finalize())
+    }
+
+    /// Writes the sample in the latest version of the `.sample` format
     pub fn serialize_into(&self, output: &mut impl Write) -> Result<()> {
-        let mut output = zstd::stream::write::Encoder::new(
-            output, 6, /* tuned to be not too big (file), not too slow (compression) */
-        )?;
-        bincode::serialize_into(&mut output, self)?;
-        output.finish()?;
-        Ok(())
+        sample_format::serialize_into(self, output)
     }
 
+    /// Reads a sample in any supported version of the `.sample` format
     pub fn deserialize_from(input: &mut impl std::io::Read) -> Result<Self> {
-        let mut input = zstd::stream::read::Decoder::new(input)?;
-        let result = bincode::deserialize_from(&mut input)?;
-        Ok(result)
+        sample_format::deserialize_from(input)
     }
 
     pub fn into_superset(self) -> SupersetSample {
         SupersetSample::new(self)
     }
 
-    pub fn into_graph(self) -> GraphSample {
-        GraphSample::new(SupersetSample::new(self))
+    pub fn into_superset_with_config(self, config: &SupersetConfig) -> SupersetSample {
+        SupersetSample::with_config(&self, config)
     }
 
     pub fn as_stripped_elf(&self) -> Result<Vec<u8>> {
@@ -287,6 +376,14 @@ mod test {
             .true_instructions
             .push(Interval::from_start_and_end(20, 30));
         classes.true_data.push(Interval::from_start_and_end(40, 50));
+        classes.function_starts.insert(0);
+        classes.function_starts.insert(20);
+        classes
+            .symbols
+            .insert(Interval::from_start_and_end(20, 30), "main".to_string());
+        classes
+            .symbols
+            .insert(Interval::from_start_and_end(40, 50), "table".to_string());
 
         let mut memory = MemoryImage::new();
 
@@ -295,7 +392,16 @@ mod test {
         let sample = ExecutableSample {
             memory,
             classes,
-            source: None,
+            source: Some(SampleSource {
+                kind: SourceKind::Debian,
+                package_name: Some("bash".to_string()),
+                package_version: Some("5.0-4".to_string()),
+                original_path: Some("./bin/bash".to_string()),
+                producer: Some("GNU C17 8.3.0 -mtune=generic -march=i686 -g -O2".to_string()),
+                fetch_date: Some(1680000000),
+            }),
+            architecture: Architecture::X86_64,
+            sections: IntervalMap::new(),
         };
 
         let mut output = Vec::new();
@@ -309,5 +415,15 @@ mod test {
             format!("{}", sample.memory.dump())
         );
         assert_eq!(sample2.source, sample.source);
+        assert_eq!(sample2.architecture, sample.architecture);
+
+        assert_eq!(sample2.classes.describe(0x1c), "code in `main`+0x8");
+        assert_eq!(sample2.classes.describe(42), "data in `table`+0x2");
+        assert_eq!(sample2.classes.describe(15), "unlabeled");
+
+        // fetching the same executable again gives the same hash
+        let mut refetched = sample2;
+        refetched.source.as_mut().unwrap().fetch_date = Some(1690000000);
+        assert_eq!(refetched.hash(), sample.hash());
     }
 }
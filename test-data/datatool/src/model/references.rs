@@ -0,0 +1,390 @@
+//! Data references of the superset nodes
+//!
+//! The immediates and displacements pointing into the image are taken as references as is
+//! (this covers the function pointers and the callbacks).
+//!
+//! The i386 PIC code doesn't have the absolute addresses in the instructions. Instead, it calls a
+//! `__x86.get_pc_thunk.*` function to get its own address and adds a constant to it to reach the GOT:
+//! ```text
+//! call __x86.get_pc_thunk.bx
+//! add ebx, _GLOBAL_OFFSET_TABLE_ - $
+//! mov eax, [ebx+foo@GOTOFF]
+//! ```
+//! Here the values produced by the thunks are propagated along the fall-through edges, which is enough
+//! to resolve the `[ebx+disp]` operands in the function they are computed in.
+
+use crate::model::InstructionFeature;
+use iced_x86::{
+    Code, DecoderOptions, InstructionInfoFactory, Mnemonic, OpAccess, OpKind, Register,
+};
+use memory_image::MemoryImage;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+/// An instruction referencing an address
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    /// Address of the instruction
+    pub source: u32,
+    /// The referenced address
+    pub target: u32,
+}
+
+fn mapped(memory: &MemoryImage, address: u64) -> Option<u32> {
+    u32::try_from(address)
+        .ok()
+        .filter(|&address| !memory.access_all_at(address).is_empty())
+}
+
+/// Address in the image pointed to by an immediate operand of `instruction`
+pub fn immediate_reference(
+    instruction: &iced_x86::Instruction,
+    memory: &MemoryImage,
+) -> Option<u32> {
+    // the narrower immediates are too likely to be just numbers
+    (0..instruction.op_count())
+        .filter(|&i| {
+            matches!(
+                instruction.op_kind(i),
+                OpKind::Immediate32 | OpKind::Immediate32to64 | OpKind::Immediate64
+            )
+        })
+        .find_map(|i| mapped(memory, instruction.immediate(i)))
+}
+
+/// Address in the image pointed to by the displacement (or the RIP-relative address) of the memory operand
+pub fn displacement_reference(
+    instruction: &iced_x86::Instruction,
+    memory: &MemoryImage,
+) -> Option<u32> {
+    if !instruction.op_kinds().any(|k| k == OpKind::Memory) {
+        return None;
+    }
+    let target = if instruction.is_ip_rel_memory_operand() {
+        instruction.ip_rel_memory_address()
+    } else {
+        instruction.memory_displacement64()
+    };
+    mapped(memory, target).filter(|&target| target != 0)
+}
+
+/// References made by the operands of `instruction` (its immediates and displacements pointing into the image)
+pub fn operand_references(
+    instruction: &iced_x86::Instruction,
+    memory: &MemoryImage,
+) -> impl Iterator<Item = Reference> {
+    let source = instruction.ip32();
+    let immediate = immediate_reference(instruction, memory);
+    let displacement =
+        displacement_reference(instruction, memory).filter(|&v| Some(v) != immediate);
+    immediate
+        .into_iter()
+        .chain(displacement)
+        .map(move |target| Reference { source, target })
+}
+
+/// What is known about a register at some point
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum Value {
+    #[default]
+    Unknown,
+    Known(u32),
+    /// Different values come from different predecessors
+    Conflicting,
+}
+
+impl Value {
+    fn join(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Unknown, v) | (v, Value::Unknown) => v,
+            (Value::Known(a), Value::Known(b)) if a == b => Value::Known(a),
+            _ => Value::Conflicting,
+        }
+    }
+
+    fn known(self) -> Option<u32> {
+        match self {
+            Value::Known(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// Values of the 32-bit GPRs, indexed by the register number
+type State = [Value; 8];
+
+fn gpr_index(reg: Register) -> Option<usize> {
+    let reg = reg.full_register32();
+    reg.is_gpr32().then(|| reg.number())
+}
+
+/// If `target` is a PC thunk (`mov reg, [esp]; ret`), returns the register it loads
+fn pc_thunk_register(memory: &MemoryImage, target: u32) -> Option<usize> {
+    match *memory.access_all_at(target) {
+        // the reg field of ModRM with rm=100 (SIB) and mod=00, ESP itself can't be loaded this way
+        [0x8b, modrm, 0x24, 0xc3, ..] if modrm & 0xc7 == 0x04 && modrm != 0x24 => {
+            Some((modrm >> 3) as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Resolves the GOT-relative memory operands (and `lea`s) of the 32-bit superset
+///
+/// The values are joined optimistically: a predecessor knowing nothing about a register doesn't
+/// prevent the others from defining it, as most of the predecessors in a superset are bogus.
+pub fn resolve_pic_references(
+    addresses: &[u32],
+    instructions: &[InstructionFeature],
+    memory: &MemoryImage,
+) -> Vec<Reference> {
+    let mut factory = InstructionInfoFactory::new();
+    // states flowing into the nodes that were not yet visited, only the non-empty ones are stored
+    let mut pending = FxHashMap::<u32, State>::default();
+    let mut references = Vec::new();
+
+    for (&address, instr) in addresses.iter().zip(instructions.iter()) {
+        let mut state = pending.remove(&address).unwrap_or_default();
+        let is_call = instr.code == Code::Call_rel32_32;
+        if !is_call && state.iter().all(|&v| v == Value::Unknown) {
+            continue;
+        }
+
+        let bytes = memory.access_all_at(address);
+        let mut decoder =
+            iced_x86::Decoder::with_ip(32, bytes, address as u64, DecoderOptions::NONE);
+        let instruction = decoder.decode();
+        let value = |reg: Register| gpr_index(reg).and_then(|i| state[i].known());
+
+        // the operands are evaluated with the state before the instruction
+        let address_operand = instruction
+            .op_kinds()
+            .any(|k| k == OpKind::Memory)
+            .then(|| {
+                if instruction.memory_index() != Register::None {
+                    return None;
+                }
+                let base = value(instruction.memory_base())?;
+                Some(base.wrapping_add(instruction.memory_displacement32()))
+            })
+            .flatten();
+        if let Some(target) = address_operand {
+            references.push(Reference {
+                source: address,
+                target,
+            });
+        }
+
+        // only the full 32-bit registers, `add bl, 5` makes the value unknown
+        let op0 = (instruction.op0_kind() == OpKind::Register
+            && instruction.op0_register().is_gpr32())
+        .then(|| instruction.op0_register().number());
+        let result = match (instruction.mnemonic(), op0, instruction.op1_kind()) {
+            (Mnemonic::Add, Some(reg), OpKind::Immediate32 | OpKind::Immediate8to32) => state[reg]
+                .known()
+                .map(|v| (reg, v.wrapping_add(instruction.immediate(1) as u32))),
+            (Mnemonic::Sub, Some(reg), OpKind::Immediate32 | OpKind::Immediate8to32) => state[reg]
+                .known()
+                .map(|v| (reg, v.wrapping_sub(instruction.immediate(1) as u32))),
+            (Mnemonic::Mov, Some(reg), OpKind::Register) => {
+                value(instruction.op1_register()).map(|v| (reg, v))
+            }
+            (Mnemonic::Lea, Some(reg), _) => address_operand.map(|v| (reg, v)),
+            (Mnemonic::Call, _, _) if is_call => {
+                pc_thunk_register(memory, instruction.near_branch32())
+                    .map(|reg| (reg, instruction.next_ip32()))
+            }
+            _ => None,
+        };
+
+        let info = factory.info_options(
+            &instruction,
+            iced_x86::InstructionInfoOptions::NO_MEMORY_USAGE,
+        );
+        for used in info.used_registers() {
+            if used.access() != OpAccess::Read && used.access() != OpAccess::CondRead {
+                if let Some(i) = gpr_index(used.register()) {
+                    state[i] = Value::Unknown;
+                }
+            }
+        }
+        if instruction.mnemonic() == Mnemonic::Call && result.is_none() {
+            // the caller-saved registers are clobbered by the callee
+            for reg in [Register::EAX, Register::ECX, Register::EDX] {
+                state[reg.number()] = Value::Unknown;
+            }
+        }
+        if let Some((reg, v)) = result {
+            state[reg] = Value::Known(v);
+        }
+
+        if instr.falls_through && state.iter().any(|&v| v != Value::Unknown) {
+            let next = address.wrapping_add(instr.size as u32);
+            let next_state = pending.entry(next).or_default();
+            for (dst, &src) in next_state.iter_mut().zip(state.iter()) {
+                *dst = dst.join(src);
+            }
+        }
+    }
+
+    references
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::graph::{RelationSet, RelationType};
+    use crate::model::{AddressClasses, ExecutableSample, GraphConfig, SupersetConfig};
+    use memory_image::Protection;
+
+    #[test]
+    fn test_operand_references() {
+        #[rustfmt::skip]
+        let code = vec![
+            0x68, 0x00, 0x20, 0x00, 0x00,             // push 0x2000
+            0xa1, 0x04, 0x20, 0x00, 0x00,             // mov eax, [0x2004]
+            0xc7, 0x05, 0x08, 0x20, 0x00, 0x00,       // mov dword [0x2008], 0x1000
+            0x00, 0x10, 0x00, 0x00,
+            0x68, 0x78, 0x56, 0x34, 0x12,             // push 0x12345678
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        memory.add_region(
+            0x2000,
+            Protection::READ_WRITE,
+            vec![0; 0x10],
+            "".to_string(),
+        );
+
+        let mut decoder = iced_x86::Decoder::with_ip(
+            32,
+            memory.access_all_at(0x1000),
+            0x1000,
+            DecoderOptions::NONE,
+        );
+        let references = decoder
+            .iter()
+            .flat_map(|instruction| operand_references(&instruction, &memory))
+            .map(|r| (r.source, r.target))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            references,
+            vec![
+                (0x1000, 0x2000),
+                (0x1005, 0x2004),
+                (0x100a, 0x1000),
+                (0x100a, 0x2008),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_negative_imm8() {
+        #[rustfmt::skip]
+        let code = vec![
+            0xe8, 0x0e, 0x00, 0x00, 0x00,             // call 0x1013
+            0x81, 0xc3, 0x0b, 0x20, 0x00, 0x00,       // add ebx, 0x200b (ebx = 0x3010)
+            0x83, 0xc3, 0xf8,                         // add ebx, -8 (ebx = 0x3008)
+            0x83, 0xeb, 0x04,                         // sub ebx, 4 (ebx = 0x3004)
+            0x8b, 0x03,                               // mov eax, [ebx]
+            0x8b, 0x1c, 0x24,                         // mov ebx, [esp] (the thunk)
+            0xc3,                                     // ret
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        memory.add_region(
+            0x3000,
+            Protection::READ_WRITE,
+            vec![0; 0x10],
+            "".to_string(),
+        );
+        let superset = ExecutableSample::new(memory.clone(), AddressClasses::new())
+            .unwrap()
+            .into_superset();
+
+        let references =
+            resolve_pic_references(&superset.addresses, &superset.instructions, &memory);
+        assert_eq!(
+            references,
+            vec![Reference {
+                source: 0x1011,
+                target: 0x3004
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pc_thunk() {
+        #[rustfmt::skip]
+        let code = vec![
+            0xe8, 0x1b, 0x00, 0x00, 0x00,             // call 0x1020
+            0x81, 0xc3, 0xfb, 0x1f, 0x00, 0x00,       // add ebx, 0x1ffb (ebx = 0x3000)
+            0x8d, 0x83, 0x00, 0xf0, 0xff, 0xff,       // lea eax, [ebx-0x1000]
+            0x8b, 0x8b, 0x08, 0x00, 0x00, 0x00,       // mov ecx, [ebx+0x8]
+            0x89, 0xc3,                               // mov ebx, eax
+            0x8b, 0x13,                               // mov edx, [ebx]
+            0x31, 0xdb,                               // xor ebx, ebx
+            0x8b, 0x53, 0x04,                         // mov edx, [ebx+0x4]
+            0x8b, 0x1c, 0x24,                         // mov ebx, [esp] (the thunk)
+            0xc3,                                     // ret
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        memory.add_region(0x2000, Protection::READ, vec![0; 0x10], "".to_string());
+        memory.add_region(
+            0x3000,
+            Protection::READ_WRITE,
+            vec![0; 0x10],
+            "".to_string(),
+        );
+        let superset = ExecutableSample::new(memory.clone(), AddressClasses::new())
+            .unwrap()
+            .into_superset();
+
+        let references =
+            resolve_pic_references(&superset.addresses, &superset.instructions, &memory);
+        assert_eq!(
+            references,
+            vec![
+                Reference {
+                    source: 0x100b,
+                    target: 0x2000
+                },
+                Reference {
+                    source: 0x1011,
+                    target: 0x3008
+                },
+                Reference {
+                    source: 0x1019,
+                    target: 0x2000
+                },
+            ]
+        );
+
+        let config = SupersetConfig {
+            resolve_pic: true,
+            ..Default::default()
+        };
+        let graph = ExecutableSample::new(memory, AddressClasses::new())
+            .unwrap()
+            .into_superset_with_config(&config)
+            .into_graph(&GraphConfig {
+                relations: RelationSet::all(),
+                ..Default::default()
+            });
+        let node = |address| {
+            superset
+                .addresses
+                .iter()
+                .position(|&v| v == address)
+                .unwrap() as u32
+        };
+        assert!(graph
+            .graph
+            .edges
+            .iter()
+            .zip(graph.graph.edge_types.iter())
+            .any(|(&edge, &ty)| edge == (node(0x1011), node(0x3008))
+                && ty == RelationType::References));
+    }
+}
@@ -0,0 +1,509 @@
+//! On-disk format of the `.sample` files
+//!
+//! A sample file starts with a fixed-size header (magic, format version and schema flags),
+//! followed by a zstd-compressed bincode of the sample.
+//!
+//! Files written before the header was introduced (version 0) are just the zstd-compressed bincode,
+//! they are detected by the absence of the magic.
+//!
+//! When changing the layout of [`ExecutableSample`] (or anything it contains), bump [`FORMAT_VERSION`]
+//! and add the previous layout to the [`legacy`] module, so that the old samples still can be read.
+//! The legacy layouts only use their own frozen types, so they are not affected by such changes.
+
+use crate::model::ExecutableSample;
+use anyhow::{bail, Context, Result};
+use bitflags::bitflags;
+use serde::de::DeserializeOwned;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+
+pub const MAGIC: [u8; 8] = *b"IX86SMPL";
+pub const FORMAT_VERSION: u32 = 5;
+const HEADER_SIZE: usize = MAGIC.len() + 4 + 4;
+
+bitflags! {
+    /// Describes which optional parts are present in the sample
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct SchemaFlags: u32 {
+        const HAS_SOURCE = 1 << 0;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SampleHeader {
+    pub version: u32,
+    pub flags: SchemaFlags,
+}
+
+impl SampleHeader {
+    fn for_sample(sample: &ExecutableSample) -> Self {
+        let mut flags = SchemaFlags::empty();
+        if sample.source.is_some() {
+            flags |= SchemaFlags::HAS_SOURCE;
+        }
+
+        Self {
+            version: FORMAT_VERSION,
+            flags,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut result = [0; HEADER_SIZE];
+        result[..8].copy_from_slice(&MAGIC);
+        result[8..12].copy_from_slice(&self.version.to_le_bytes());
+        result[12..16].copy_from_slice(&self.flags.bits().to_le_bytes());
+        result
+    }
+}
+
+impl Display for SampleHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{} {:?}", self.version, self.flags)
+    }
+}
+
+/// Reads the header, returning it along with the bytes that should be fed to the decompressor
+///
+/// For legacy (headerless) samples the bytes read while looking for the magic are part of the payload
+fn read_header(input: &mut impl Read) -> Result<(SampleHeader, Vec<u8>)> {
+    let mut buffer = Vec::with_capacity(HEADER_SIZE);
+    input
+        .by_ref()
+        .take(HEADER_SIZE as u64)
+        .read_to_end(&mut buffer)
+        .context("Reading the sample header")?;
+
+    if buffer.len() < HEADER_SIZE || buffer[..8] != MAGIC {
+        return Ok((
+            SampleHeader {
+                version: 0,
+                flags: SchemaFlags::empty(),
+            },
+            buffer,
+        ));
+    }
+
+    let version = u32::from_le_bytes(buffer[8..12].try_into().unwrap());
+    let flags = u32::from_le_bytes(buffer[12..16].try_into().unwrap());
+
+    if version == 0 || version > FORMAT_VERSION {
+        bail!(
+            "Unsupported sample format version {} (the latest supported is {})",
+            version,
+            FORMAT_VERSION
+        );
+    }
+    let Some(flags) = SchemaFlags::from_bits(flags) else {
+        bail!("Unknown schema flags: {:#x}", flags);
+    };
+
+    Ok((SampleHeader { version, flags }, Vec::new()))
+}
+
+fn decode<T: DeserializeOwned>(prefix: Vec<u8>, input: &mut impl Read) -> Result<T> {
+    let mut input = zstd::stream::read::Decoder::new(prefix.as_slice().chain(input))?;
+    let result = bincode::deserialize_from(&mut input)?;
+    Ok(result)
+}
+
+pub fn serialize_into(sample: &ExecutableSample, output: &mut impl Write) -> Result<()> {
+    output.write_all(&SampleHeader::for_sample(sample).to_bytes())?;
+
+    let mut output = zstd::stream::write::Encoder::new(
+        output, 6, /* tuned to be not too big (file), not too slow (compression) */
+    )?;
+    bincode::serialize_into(&mut output, sample)?;
+    output.finish()?;
+    Ok(())
+}
+
+pub fn deserialize_from(input: &mut impl Read) -> Result<ExecutableSample> {
+    let (header, prefix) = read_header(input)?;
+
+    let sample = match header.version {
+        0 => {
+            let sample = decode::<legacy::SampleV0>(prefix, input)?;
+            let sample = legacy::SampleV2::from(legacy::SampleV1::from(sample));
+            legacy::SampleV4::from(legacy::SampleV3::from(sample)).into()
+        }
+        1 => {
+            let sample = decode::<legacy::SampleV1>(prefix, input)?;
+            let sample = legacy::SampleV3::from(legacy::SampleV2::from(sample));
+            legacy::SampleV4::from(sample).into()
+        }
+        2 => {
+            let sample = decode::<legacy::SampleV2>(prefix, input)?;
+            legacy::SampleV4::from(legacy::SampleV3::from(sample)).into()
+        }
+        3 => legacy::SampleV4::from(decode::<legacy::SampleV3>(prefix, input)?).into(),
+        4 => decode::<legacy::SampleV4>(prefix, input)?.into(),
+        FORMAT_VERSION => decode::<ExecutableSample>(prefix, input)?,
+        _ => unreachable!(),
+    };
+
+    // the flags are derived from the sample when writing, so a mismatch means a corrupted header
+    let expected = SampleHeader::for_sample(&sample).flags;
+    if header.version != 0 && header.flags != expected {
+        bail!(
+            "The schema flags {:?} don't match the sample contents ({:?})",
+            header.flags,
+            expected
+        );
+    }
+
+    Ok(sample)
+}
+
+/// Reads only the header of the sample, without decoding the contents
+pub fn deserialize_header_from(input: &mut impl Read) -> Result<SampleHeader> {
+    read_header(input).map(|(header, _)| header)
+}
+
+/// Layouts of the previous format versions along with the conversions to the next version
+///
+/// The types here are frozen copies of the ones at the time of the version, they are converted to
+/// the current types only at the end of the chain.
+mod legacy {
+    use crate::model::interval_set::{IntervalMap, IntervalSet};
+    use crate::model::{AddressClasses, Architecture, ExecutableSample, SampleSource, SourceKind};
+    use memory_image::MemoryImage;
+    use serde::Deserialize;
+    use std::collections::BTreeSet;
+
+    #[derive(Deserialize)]
+    pub enum SourceKindV1 {
+        Debian,
+        Byteweight,
+        Unknown,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SampleSourceV1 {
+        pub kind: SourceKindV1,
+        pub package_name: Option<String>,
+        pub package_version: Option<String>,
+        pub original_path: Option<String>,
+        pub producer: Option<String>,
+        pub fetch_date: Option<u64>,
+    }
+
+    impl From<SampleSourceV1> for SampleSource {
+        fn from(value: SampleSourceV1) -> Self {
+            SampleSource {
+                kind: match value.kind {
+                    SourceKindV1::Debian => SourceKind::Debian,
+                    SourceKindV1::Byteweight => SourceKind::Byteweight,
+                    SourceKindV1::Unknown => SourceKind::Unknown,
+                },
+                package_name: value.package_name,
+                package_version: value.package_version,
+                original_path: value.original_path,
+                producer: value.producer,
+                fetch_date: value.fetch_date,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct AddressClassesV0 {
+        pub true_instructions: IntervalSet<u32>,
+        pub true_data: IntervalSet<u32>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SampleV0 {
+        pub memory: MemoryImage,
+        pub classes: AddressClassesV0,
+    }
+
+    impl From<SampleV0> for SampleV1 {
+        fn from(value: SampleV0) -> Self {
+            SampleV1 {
+                memory: value.memory,
+                classes: value.classes,
+                source: None,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct SampleV1 {
+        pub memory: MemoryImage,
+        pub classes: AddressClassesV0,
+        pub source: Option<SampleSourceV1>,
+    }
+
+    impl From<SampleV1> for SampleV2 {
+        fn from(value: SampleV1) -> Self {
+            SampleV2 {
+                memory: value.memory,
+                classes: AddressClassesV1 {
+                    true_instructions: value.classes.true_instructions,
+                    true_data: value.classes.true_data,
+                    // the function starts were not stored and can't be recovered from the merged intervals,
+                    // such samples are not scored on the functions (see `evaluate::evaluate_result`)
+                    function_starts: BTreeSet::new(),
+                },
+                source: value.source,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct AddressClassesV1 {
+        pub true_instructions: IntervalSet<u32>,
+        pub true_data: IntervalSet<u32>,
+        pub function_starts: BTreeSet<u32>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SampleV2 {
+        pub memory: MemoryImage,
+        pub classes: AddressClassesV1,
+        pub source: Option<SampleSourceV1>,
+    }
+
+    impl From<SampleV2> for SampleV3 {
+        fn from(value: SampleV2) -> Self {
+            SampleV3 {
+                memory: value.memory,
+                classes: AddressClassesV3 {
+                    true_instructions: value.classes.true_instructions,
+                    true_data: value.classes.true_data,
+                    function_starts: value.classes.function_starts,
+                    // the symbol names were not stored
+                    symbols: IntervalMap::new(),
+                },
+                source: value.source,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct AddressClassesV3 {
+        pub true_instructions: IntervalSet<u32>,
+        pub true_data: IntervalSet<u32>,
+        pub function_starts: BTreeSet<u32>,
+        pub symbols: IntervalMap<u32, String>,
+    }
+
+    impl From<AddressClassesV3> for AddressClasses {
+        fn from(value: AddressClassesV3) -> Self {
+            AddressClasses {
+                true_instructions: value.true_instructions,
+                true_data: value.true_data,
+                function_starts: value.function_starts,
+                symbols: value.symbols,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct SampleV3 {
+        pub memory: MemoryImage,
+        pub classes: AddressClassesV3,
+        pub source: Option<SampleSourceV1>,
+    }
+
+    impl From<SampleV3> for SampleV4 {
+        fn from(value: SampleV3) -> Self {
+            SampleV4 {
+                memory: value.memory,
+                classes: value.classes,
+                source: value.source,
+                // only 32-bit samples were collected before the architecture was stored
+                architecture: ArchitectureV4::X86_32,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub enum ArchitectureV4 {
+        X86_16,
+        X86_32,
+        X86_64,
+    }
+
+    impl From<ArchitectureV4> for Architecture {
+        fn from(value: ArchitectureV4) -> Self {
+            match value {
+                ArchitectureV4::X86_16 => Architecture::X86_16,
+                ArchitectureV4::X86_32 => Architecture::X86_32,
+                ArchitectureV4::X86_64 => Architecture::X86_64,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct SampleV4 {
+        pub memory: MemoryImage,
+        pub classes: AddressClassesV3,
+        pub source: Option<SampleSourceV1>,
+        pub architecture: ArchitectureV4,
+    }
+
+    impl From<SampleV4> for ExecutableSample {
+        fn from(value: SampleV4) -> Self {
+            ExecutableSample {
+                memory: value.memory,
+                classes: value.classes.into(),
+                source: value.source.map(Into::into),
+                architecture: value.architecture.into(),
+                // the section names were not stored
+                sections: IntervalMap::new(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::interval_set::Interval;
+    use crate::model::{AddressClasses, Architecture, SampleSource, SourceKind};
+    use memory_image::{MemoryImage, Protection};
+
+    fn sample() -> ExecutableSample {
+        let mut classes = AddressClasses::new();
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0, 10));
+        classes.true_data.push(Interval::from_start_and_end(40, 50));
+        classes.function_starts.insert(0);
+
+        let mut memory = MemoryImage::new();
+        memory.add_region(0, Protection::READ_EXECUTE, vec![0x90; 60], "".to_string());
+
+        ExecutableSample::new(memory, classes).unwrap()
+    }
+
+    #[test]
+    fn test_header() {
+        let sample = sample();
+
+        let mut output = Vec::new();
+        serialize_into(&sample, &mut output).unwrap();
+        assert_eq!(output[..8], MAGIC);
+
+        let header = deserialize_header_from(&mut output.as_slice()).unwrap();
+        assert_eq!(
+            header,
+            SampleHeader {
+                version: FORMAT_VERSION,
+                flags: SchemaFlags::empty(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_legacy_v0() {
+        let sample = sample();
+
+        // that's how the samples were written before the header was introduced
+        let mut output = Vec::new();
+        let mut encoder = zstd::stream::write::Encoder::new(&mut output, 6).unwrap();
+        bincode::serialize_into(
+            &mut encoder,
+            &(
+                &sample.memory,
+                &sample.classes.true_instructions,
+                &sample.classes.true_data,
+            ),
+        )
+        .unwrap();
+        encoder.finish().unwrap();
+
+        let header = deserialize_header_from(&mut output.as_slice()).unwrap();
+        assert_eq!(header.version, 0);
+
+        let sample2 = deserialize_from(&mut output.as_slice()).unwrap();
+        assert_eq!(
+            sample2.classes.true_instructions,
+            sample.classes.true_instructions
+        );
+        assert_eq!(sample2.classes.true_data, sample.classes.true_data);
+        assert!(sample2.classes.function_starts.is_empty());
+        assert_eq!(
+            format!("{}", sample2.memory.dump()),
+            format!("{}", sample.memory.dump())
+        );
+        assert_eq!(sample2.source, None);
+    }
+
+    #[test]
+    fn test_legacy_v2() {
+        let mut sample = sample();
+        sample
+            .classes
+            .symbols
+            .insert(Interval::from_start_and_end(0, 10), "main".to_string());
+        let mut source = SampleSource::new(SourceKind::Debian);
+        source.package_name = Some("coreutils".to_string());
+        sample.source = Some(source);
+
+        // version 2 had everything but the symbol names
+        let mut output = Vec::new();
+        output.extend_from_slice(
+            &SampleHeader {
+                version: 2,
+                flags: SchemaFlags::HAS_SOURCE,
+            }
+            .to_bytes(),
+        );
+        let mut encoder = zstd::stream::write::Encoder::new(&mut output, 6).unwrap();
+        bincode::serialize_into(
+            &mut encoder,
+            &(
+                &sample.memory,
+                (
+                    &sample.classes.true_instructions,
+                    &sample.classes.true_data,
+                    &sample.classes.function_starts,
+                ),
+                &sample.source,
+            ),
+        )
+        .unwrap();
+        encoder.finish().unwrap();
+
+        let sample2 = deserialize_from(&mut output.as_slice()).unwrap();
+        assert_eq!(
+            sample2.classes.true_instructions,
+            sample.classes.true_instructions
+        );
+        assert_eq!(
+            sample2.classes.function_starts,
+            sample.classes.function_starts
+        );
+        assert!(sample2.classes.symbols.is_empty());
+        assert_eq!(sample2.source, sample.source);
+        assert_eq!(sample2.architecture, Architecture::X86_32);
+    }
+
+    #[test]
+    fn test_schema_flags() {
+        let mut output = Vec::new();
+        serialize_into(&sample(), &mut output).unwrap();
+
+        let mut unknown = output.clone();
+        unknown[12..16].copy_from_slice(&(1u32 << 31).to_le_bytes());
+        assert!(deserialize_header_from(&mut unknown.as_slice()).is_err());
+        assert!(deserialize_from(&mut unknown.as_slice()).is_err());
+
+        // the sample has no source
+        let mut mismatch = output;
+        mismatch[12..16].copy_from_slice(&SchemaFlags::HAS_SOURCE.bits().to_le_bytes());
+        assert!(deserialize_header_from(&mut mismatch.as_slice()).is_ok());
+        assert!(deserialize_from(&mut mismatch.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_future_version() {
+        let mut output = Vec::new();
+        serialize_into(&sample(), &mut output).unwrap();
+        output[8..12].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        assert!(deserialize_from(&mut output.as_slice()).is_err());
+    }
+}
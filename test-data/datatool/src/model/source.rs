@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SourceKind {
+    Debian,
+    Byteweight,
+    Unknown,
+}
+
+impl Display for SourceKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceKind::Debian => write!(f, "debian"),
+            SourceKind::Byteweight => write!(f, "byteweight"),
+            SourceKind::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Describes where a sample came from
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SampleSource {
+    pub kind: SourceKind,
+    pub package_name: Option<String>,
+    pub package_version: Option<String>,
+    /// Path of the executable inside the package (or the dataset)
+    pub original_path: Option<String>,
+    /// Compiler that produced the executable, as reported by DW_AT_producer or the .comment section
+    pub producer: Option<String>,
+    /// Seconds since the unix epoch
+    pub fetch_date: Option<u64>,
+}
+
+impl SampleSource {
+    pub fn new(kind: SourceKind) -> Self {
+        Self {
+            kind,
+            package_name: None,
+            package_version: None,
+            original_path: None,
+            producer: None,
+            fetch_date: Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+            ),
+        }
+    }
+
+    /// A key that identifies the package the sample comes from
+    ///
+    /// Samples from the same package are likely to share code, so they should be grouped together
+    pub fn package_key(&self) -> Option<String> {
+        self.package_name
+            .as_ref()
+            .map(|name| format!("{}/{}", self.kind, name))
+    }
+}
+
+impl Display for SampleSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Kind: {}", self.kind)?;
+        if let Some(package_name) = &self.package_name {
+            writeln!(f, "Package: {}", package_name)?;
+        }
+        if let Some(package_version) = &self.package_version {
+            writeln!(f, "Version: {}", package_version)?;
+        }
+        if let Some(original_path) = &self.original_path {
+            writeln!(f, "Path: {}", original_path)?;
+        }
+        if let Some(producer) = &self.producer {
+            writeln!(f, "Producer: {}", producer)?;
+        }
+        if let Some(fetch_date) = self.fetch_date {
+            writeln!(f, "Fetched at: {} (unix time)", fetch_date)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+//! Stack effects of the superset nodes, used to track the values spilled to the stack
+//!
+//! The unoptimized code keeps its locals in the stack frame, so most of its data flow goes through
+//! the memory instead of the registers:
+//! ```text
+//! mov [ebp-4], eax
+//! ...
+//! mov ecx, [ebp-4]
+//! ```
+//! The graph builder follows the stack and frame pointers as offsets from an arbitrary origin along
+//! each path, which lets it match such stores and loads by the slot offset.
+
+use iced_x86::{InstructionInfoFactory, Mnemonic, OpAccess, OpKind, Register};
+use serde::{Deserialize, Serialize};
+
+/// How an instruction changes the stack pointer
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StackPointerEffect {
+    /// `esp += delta`, like `push`, `pop` or `sub esp, 0x10`
+    Delta(i32),
+    /// `esp = ebp + offset`, like `mov esp, ebp` or `leave`
+    FromFramePointer(i32),
+    Unknown,
+}
+
+/// How an instruction changes the frame pointer
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FramePointerEffect {
+    Unchanged,
+    /// `ebp = esp + offset`, like `mov ebp, esp`
+    FromStackPointer(i32),
+    Unknown,
+}
+
+/// A stack slot, addressed relative to the value of a register before the instruction
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StackSlot {
+    StackPointer(i32),
+    FramePointer(i32),
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StackEffect {
+    pub stack_pointer: StackPointerEffect,
+    pub frame_pointer: FramePointerEffect,
+    /// The first stack slot read by the instruction
+    pub load: Option<StackSlot>,
+    /// The first stack slot written by the instruction (including the `push`es and `call`s)
+    pub store: Option<StackSlot>,
+}
+
+fn is_stack_pointer(register: Register) -> bool {
+    matches!(register, Register::ESP | Register::RSP)
+}
+
+fn is_frame_pointer(register: Register) -> bool {
+    matches!(register, Register::EBP | Register::RBP)
+}
+
+fn slot(base: Register, displacement: i32) -> Option<StackSlot> {
+    if is_stack_pointer(base) {
+        Some(StackSlot::StackPointer(displacement))
+    } else if is_frame_pointer(base) {
+        Some(StackSlot::FramePointer(displacement))
+    } else {
+        None
+    }
+}
+
+/// The `[esp+disp]` or `[ebp+disp]` memory operand of `lea`
+fn lea_slot(instruction: &iced_x86::Instruction) -> Option<StackSlot> {
+    if instruction.mnemonic() != Mnemonic::Lea || instruction.memory_index() != Register::None {
+        return None;
+    }
+    slot(
+        instruction.memory_base(),
+        instruction.memory_displacement64() as i32,
+    )
+}
+
+/// The register copied by `mov reg, reg`
+fn moved_register(instruction: &iced_x86::Instruction) -> Option<Register> {
+    (instruction.mnemonic() == Mnemonic::Mov && instruction.op1_kind() == OpKind::Register)
+        .then(|| instruction.op1_register())
+}
+
+impl StackEffect {
+    /// The `factory` allocates, so it should be reused between the instructions
+    pub fn new(
+        instruction: &iced_x86::Instruction,
+        bitness: u32,
+        factory: &mut InstructionInfoFactory,
+    ) -> Self {
+        let info = factory.info(instruction);
+
+        let mut load = None;
+        let mut store = None;
+        for used_memory in info.used_memory() {
+            if used_memory.index() != Register::None {
+                continue;
+            }
+            // the displacement is sign-extended to 64 bits
+            let Some(slot) = slot(used_memory.base(), used_memory.displacement() as i32) else {
+                continue;
+            };
+            match used_memory.access() {
+                OpAccess::Read | OpAccess::CondRead => {
+                    load.get_or_insert(slot);
+                }
+                OpAccess::Write | OpAccess::CondWrite => {
+                    store.get_or_insert(slot);
+                }
+                OpAccess::ReadWrite | OpAccess::ReadCondWrite => {
+                    load.get_or_insert(slot);
+                    store.get_or_insert(slot);
+                }
+                _ => {}
+            }
+        }
+
+        let writes = |predicate: fn(Register) -> bool| {
+            info.used_registers().iter().any(|r| {
+                predicate(r.register())
+                    && matches!(
+                        r.access(),
+                        OpAccess::Write
+                            | OpAccess::CondWrite
+                            | OpAccess::ReadWrite
+                            | OpAccess::ReadCondWrite
+                    )
+            })
+        };
+        let word_size = (bitness / 8) as i32;
+        let writes_stack_pointer = writes(is_stack_pointer);
+        let writes_frame_pointer = writes(is_frame_pointer);
+        let destination = (instruction.op_count() > 0
+            && instruction.op0_kind() == OpKind::Register)
+            .then(|| instruction.op0_register());
+
+        let stack_pointer = match instruction.mnemonic() {
+            // mov esp, ebp; pop ebp
+            Mnemonic::Leave => StackPointerEffect::FromFramePointer(word_size),
+            _ if instruction.is_stack_instruction() => {
+                StackPointerEffect::Delta(instruction.stack_pointer_increment())
+            }
+            _ if !writes_stack_pointer => StackPointerEffect::Delta(0),
+            Mnemonic::Add | Mnemonic::Sub
+                if destination.is_some_and(is_stack_pointer)
+                    && matches!(
+                        instruction.op1_kind(),
+                        OpKind::Immediate8to32
+                            | OpKind::Immediate32
+                            | OpKind::Immediate8to64
+                            | OpKind::Immediate32to64
+                    ) =>
+            {
+                let immediate = instruction.immediate(1) as i32;
+                if instruction.mnemonic() == Mnemonic::Add {
+                    StackPointerEffect::Delta(immediate)
+                } else {
+                    StackPointerEffect::Delta(immediate.wrapping_neg())
+                }
+            }
+            _ if !destination.is_some_and(is_stack_pointer) => StackPointerEffect::Unknown,
+            _ => match (moved_register(instruction), lea_slot(instruction)) {
+                (Some(source), _) if is_frame_pointer(source) => {
+                    StackPointerEffect::FromFramePointer(0)
+                }
+                (_, Some(StackSlot::StackPointer(offset))) => StackPointerEffect::Delta(offset),
+                (_, Some(StackSlot::FramePointer(offset))) => {
+                    StackPointerEffect::FromFramePointer(offset)
+                }
+                _ => StackPointerEffect::Unknown,
+            },
+        };
+
+        let frame_pointer = match instruction.mnemonic() {
+            // push ebp; mov ebp, esp; sub esp, N
+            Mnemonic::Enter => FramePointerEffect::FromStackPointer(-word_size),
+            _ if !writes_frame_pointer => FramePointerEffect::Unchanged,
+            _ if !destination.is_some_and(is_frame_pointer) => FramePointerEffect::Unknown,
+            _ => match (moved_register(instruction), lea_slot(instruction)) {
+                (Some(source), _) if is_stack_pointer(source) => {
+                    FramePointerEffect::FromStackPointer(0)
+                }
+                (_, Some(StackSlot::StackPointer(offset))) => {
+                    FramePointerEffect::FromStackPointer(offset)
+                }
+                _ => FramePointerEffect::Unknown,
+            },
+        };
+
+        StackEffect {
+            stack_pointer,
+            frame_pointer,
+            load,
+            store,
+        }
+    }
+
+    /// The effect of a call as seen by its return site, the callee has popped the return address
+    pub fn after_return(self) -> Self {
+        StackEffect {
+            stack_pointer: StackPointerEffect::Delta(0),
+            store: None,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use iced_x86::DecoderOptions;
+
+    fn effects(bitness: u32, code: &[u8]) -> Vec<StackEffect> {
+        let mut factory = InstructionInfoFactory::new();
+        iced_x86::Decoder::new(bitness, code, DecoderOptions::NONE)
+            .into_iter()
+            .map(|instruction| StackEffect::new(&instruction, bitness, &mut factory))
+            .collect()
+    }
+
+    #[test]
+    fn test_frame() {
+        #[rustfmt::skip]
+        let code = [
+            0x55,                   // push ebp
+            0x89, 0xe5,             // mov ebp, esp
+            0x83, 0xec, 0x10,       // sub esp, 0x10
+            0x89, 0x45, 0xfc,       // mov [ebp-4], eax
+            0x8b, 0x4c, 0x24, 0x08, // mov ecx, [esp+8]
+            0x83, 0xe4, 0xf0,       // and esp, -16
+            0xc9,                   // leave
+            0xc3,                   // ret
+        ];
+        let effects = effects(32, &code);
+        use FramePointerEffect as Fp;
+        use StackPointerEffect as Sp;
+
+        assert_eq!(effects[0].stack_pointer, Sp::Delta(-4));
+        assert_eq!(effects[0].frame_pointer, Fp::Unchanged);
+        assert_eq!(effects[0].store, Some(StackSlot::StackPointer(-4)));
+        assert_eq!(effects[1].stack_pointer, Sp::Delta(0));
+        assert_eq!(effects[1].frame_pointer, Fp::FromStackPointer(0));
+        assert_eq!(effects[2].stack_pointer, Sp::Delta(-0x10));
+        assert_eq!(effects[3].store, Some(StackSlot::FramePointer(-4)));
+        assert_eq!(effects[3].load, None);
+        assert_eq!(effects[4].load, Some(StackSlot::StackPointer(8)));
+        assert_eq!(effects[5].stack_pointer, Sp::Unknown);
+        assert_eq!(effects[6].stack_pointer, Sp::FromFramePointer(4));
+        assert_eq!(effects[6].frame_pointer, Fp::Unknown);
+        assert_eq!(effects[7].stack_pointer, Sp::Delta(4));
+        assert_eq!(effects[7].load, Some(StackSlot::StackPointer(0)));
+    }
+
+    #[test]
+    fn test_64bit() {
+        #[rustfmt::skip]
+        let code = [
+            0x48, 0x83, 0xec, 0x28,       // sub rsp, 0x28
+            0x48, 0x8d, 0x6c, 0x24, 0x20, // lea rbp, [rsp+0x20]
+            0x48, 0x89, 0x7d, 0xf8,       // mov [rbp-8], rdi
+        ];
+        let effects = effects(64, &code);
+
+        assert_eq!(effects[0].stack_pointer, StackPointerEffect::Delta(-0x28));
+        assert_eq!(
+            effects[1].frame_pointer,
+            FramePointerEffect::FromStackPointer(0x20)
+        );
+        assert_eq!(effects[2].store, Some(StackSlot::FramePointer(-8)));
+    }
+}
@@ -1,22 +1,60 @@
-use crate::model::{ExecutableSample, GraphSample};
+use crate::model::interval_set::{Interval, IntervalSet};
+use crate::model::jump_table::{global_offset_table, resolve_jump_table};
+use crate::model::references::{
+    displacement_reference, immediate_reference, operand_references, resolve_pic_references,
+};
+use crate::model::stack_slots::StackEffect;
+use crate::model::{
+    AddressClasses, ExecutableSample, GraphConfig, GraphSample, JumpTable, Reference,
+};
+use anyhow::bail;
 use bitflags::bitflags;
 use enum_map::Enum;
-use iced_x86::{Code, DecoderOptions, InstructionInfoFactory, OpAccess, RflagsBits};
-use itertools::Itertools;
+use iced_x86::{
+    Code, DecoderOptions, InstructionInfoFactory, Mnemonic, OpAccess, OpKind, RflagsBits,
+};
+use memory_image::{MemoryImage, MemoryImageItem, Protection};
+use num_enum::TryFromPrimitive;
 use parquet::basic::Compression;
 use parquet::file::properties::WriterProperties;
 use parquet::file::writer::SerializedFileWriter;
+use parquet::format::KeyValue;
 use parquet::record::RecordWriter;
 use parquet_derive::ParquetRecordWriter;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
 use std::io::Write;
+use std::str::FromStr;
 use std::sync::Arc;
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
+#[derive(Serialize, Deserialize, TryFromPrimitive, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
 pub enum Label {
-    Code,
-    NotCode,
+    /// The first byte of an instruction
+    InstructionStart = 0,
+    /// A byte inside of an instruction, but not the first one
+    InstructionBody = 1,
+    Data = 2,
+    /// Alignment padding between the code (runs of nops or int3s not covered by the ground truth)
+    Padding = 3,
+    /// Not covered by the ground truth
+    Unknown = 4,
+}
+
+impl Label {
+    /// Whether the superset instruction at this address is a true instruction
+    pub fn is_code(self) -> bool {
+        self == Label::InstructionStart
+    }
+
+    /// Whether the predictions at this address should be scored
+    ///
+    /// There is no way to tell whether a disassembler is right about padding or unlabeled bytes
+    pub fn is_scored(self) -> bool {
+        !matches!(self, Label::Padding | Label::Unknown)
+    }
 }
 
 #[derive(Enum, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -36,27 +74,146 @@ pub enum UsedRegister {
     Af = 10,
     Zf = 11,
     Sf = 12,
+
+    // only available in 64-bit mode
+    R8 = 13,
+    R9 = 14,
+    R10 = 15,
+    R11 = 16,
+    R12 = 17,
+    R13 = 18,
+    R14 = 19,
+    R15 = 20,
+
+    Of = 21,
+    Df = 22,
+
+    Xmm0 = 23,
+    Xmm1 = 24,
+    Xmm2 = 25,
+    Xmm3 = 26,
+    Xmm4 = 27,
+    Xmm5 = 28,
+    Xmm6 = 29,
+    Xmm7 = 30,
+
+    /// The whole x87 stack (along with the MMX registers aliasing it)
+    X87 = 31,
+
+    Es = 32,
+    Cs = 33,
+    Ss = 34,
+    Ds = 35,
+    Fs = 36,
+    Gs = 37,
 }
 
 bitflags! {
-    #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
-    pub struct RegisterSet: u16 {
-        // track only full-size registers
-        const EAX = 1 << UsedRegister::Eax as u16;
-        const ECX = 1 << UsedRegister::Ecx as u16;
-        const EDX = 1 << UsedRegister::Edx as u16;
-        const EBX = 1 << UsedRegister::Ebx as u16;
-        const ESP = 1 << UsedRegister::Esp as u16;
-        const EBP = 1 << UsedRegister::Ebp as u16;
-        const ESI = 1 << UsedRegister::Esi as u16;
-        const EDI = 1 << UsedRegister::Edi as u16;
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RegisterSet: u64 {
+        // track only full-size registers (the 64-bit ones are tracked as their 32-bit halves)
+        const EAX = 1 << UsedRegister::Eax as u64;
+        const ECX = 1 << UsedRegister::Ecx as u64;
+        const EDX = 1 << UsedRegister::Edx as u64;
+        const EBX = 1 << UsedRegister::Ebx as u64;
+        const ESP = 1 << UsedRegister::Esp as u64;
+        const EBP = 1 << UsedRegister::Ebp as u64;
+        const ESI = 1 << UsedRegister::Esi as u64;
+        const EDI = 1 << UsedRegister::Edi as u64;
 
         // track status flags separately
-        const CF = 1 << UsedRegister::Cf as u16;
-        const PF = 1 << UsedRegister::Pf as u16;
-        const AF = 1 << UsedRegister::Af as u16;
-        const ZF = 1 << UsedRegister::Zf as u16;
-        const SF = 1 << UsedRegister::Sf as u16;
+        const CF = 1 << UsedRegister::Cf as u64;
+        const PF = 1 << UsedRegister::Pf as u64;
+        const AF = 1 << UsedRegister::Af as u64;
+        const ZF = 1 << UsedRegister::Zf as u64;
+        const SF = 1 << UsedRegister::Sf as u64;
+
+        const R8 = 1 << UsedRegister::R8 as u64;
+        const R9 = 1 << UsedRegister::R9 as u64;
+        const R10 = 1 << UsedRegister::R10 as u64;
+        const R11 = 1 << UsedRegister::R11 as u64;
+        const R12 = 1 << UsedRegister::R12 as u64;
+        const R13 = 1 << UsedRegister::R13 as u64;
+        const R14 = 1 << UsedRegister::R14 as u64;
+        const R15 = 1 << UsedRegister::R15 as u64;
+
+        const OF = 1 << UsedRegister::Of as u64;
+        const DF = 1 << UsedRegister::Df as u64;
+
+        // the YMM and ZMM registers are tracked as their XMM parts
+        const XMM0 = 1 << UsedRegister::Xmm0 as u64;
+        const XMM1 = 1 << UsedRegister::Xmm1 as u64;
+        const XMM2 = 1 << UsedRegister::Xmm2 as u64;
+        const XMM3 = 1 << UsedRegister::Xmm3 as u64;
+        const XMM4 = 1 << UsedRegister::Xmm4 as u64;
+        const XMM5 = 1 << UsedRegister::Xmm5 as u64;
+        const XMM6 = 1 << UsedRegister::Xmm6 as u64;
+        const XMM7 = 1 << UsedRegister::Xmm7 as u64;
+
+        const X87 = 1 << UsedRegister::X87 as u64;
+
+        const ES = 1 << UsedRegister::Es as u64;
+        const CS = 1 << UsedRegister::Cs as u64;
+        const SS = 1 << UsedRegister::Ss as u64;
+        const DS = 1 << UsedRegister::Ds as u64;
+        const FS = 1 << UsedRegister::Fs as u64;
+        const GS = 1 << UsedRegister::Gs as u64;
+
+        const GPRS = Self::EAX.bits() | Self::ECX.bits() | Self::EDX.bits() | Self::EBX.bits()
+            | Self::ESP.bits() | Self::EBP.bits() | Self::ESI.bits() | Self::EDI.bits()
+            | Self::R8.bits() | Self::R9.bits() | Self::R10.bits() | Self::R11.bits()
+            | Self::R12.bits() | Self::R13.bits() | Self::R14.bits() | Self::R15.bits();
+        const FLAGS = Self::CF.bits() | Self::PF.bits() | Self::AF.bits() | Self::ZF.bits()
+            | Self::SF.bits() | Self::OF.bits() | Self::DF.bits();
+        const XMMS = Self::XMM0.bits() | Self::XMM1.bits() | Self::XMM2.bits() | Self::XMM3.bits()
+            | Self::XMM4.bits() | Self::XMM5.bits() | Self::XMM6.bits() | Self::XMM7.bits();
+        const SEGMENTS = Self::ES.bits() | Self::CS.bits() | Self::SS.bits() | Self::DS.bits()
+            | Self::FS.bits() | Self::GS.bits();
+    }
+}
+
+bitflags! {
+    /// Groups of registers that are tracked for the data dependencies
+    ///
+    /// Each tracked register adds to the memory used by the graph construction
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RegisterClasses: u8 {
+        const GPR = 1 << 0;
+        /// Status flags and DF
+        const FLAGS = 1 << 1;
+        /// XMM0-XMM7
+        const SSE = 1 << 2;
+        /// x87 stack and MMX
+        const X87 = 1 << 3;
+        const SEGMENT = 1 << 4;
+    }
+}
+
+impl Default for RegisterClasses {
+    fn default() -> Self {
+        RegisterClasses::GPR | RegisterClasses::FLAGS
+    }
+}
+
+impl RegisterClasses {
+    pub fn registers(self) -> RegisterSet {
+        let mut set = RegisterSet::empty();
+        if self.contains(RegisterClasses::GPR) {
+            set |= RegisterSet::GPRS;
+        }
+        if self.contains(RegisterClasses::FLAGS) {
+            set |= RegisterSet::FLAGS;
+        }
+        if self.contains(RegisterClasses::SSE) {
+            set |= RegisterSet::XMMS;
+        }
+        if self.contains(RegisterClasses::X87) {
+            set |= RegisterSet::X87;
+        }
+        if self.contains(RegisterClasses::SEGMENT) {
+            set |= RegisterSet::SEGMENTS;
+        }
+        set
     }
 }
 
@@ -86,6 +243,12 @@ impl RegisterSet {
         if rflags & RflagsBits::SF as u32 != 0 {
             set |= RegisterSet::SF;
         }
+        if rflags & RflagsBits::OF as u32 != 0 {
+            set |= RegisterSet::OF;
+        }
+        if rflags & RflagsBits::DF as u32 != 0 {
+            set |= RegisterSet::DF;
+        }
         set
     }
 }
@@ -93,16 +256,45 @@ impl RegisterSet {
 impl From<iced_x86::Register> for RegisterSet {
     fn from(reg: iced_x86::Register) -> Self {
         use iced_x86::Register::*;
-        match reg.full_register32() {
-            EAX => RegisterSet::EAX,
-            ECX => RegisterSet::ECX,
-            EDX => RegisterSet::EDX,
-            EBX => RegisterSet::EBX,
-            ESP => RegisterSet::ESP,
-            EBP => RegisterSet::EBP,
-            ESI => RegisterSet::ESI,
-            EDI => RegisterSet::EDI,
-            _ => RegisterSet::empty(),
+        if reg.is_gpr() {
+            match reg.full_register32() {
+                EAX => RegisterSet::EAX,
+                ECX => RegisterSet::ECX,
+                EDX => RegisterSet::EDX,
+                EBX => RegisterSet::EBX,
+                ESP => RegisterSet::ESP,
+                EBP => RegisterSet::EBP,
+                ESI => RegisterSet::ESI,
+                EDI => RegisterSet::EDI,
+                R8D => RegisterSet::R8,
+                R9D => RegisterSet::R9,
+                R10D => RegisterSet::R10,
+                R11D => RegisterSet::R11,
+                R12D => RegisterSet::R12,
+                R13D => RegisterSet::R13,
+                R14D => RegisterSet::R14,
+                R15D => RegisterSet::R15,
+                _ => RegisterSet::empty(),
+            }
+        } else if reg.is_segment_register() {
+            match reg {
+                ES => RegisterSet::ES,
+                CS => RegisterSet::CS,
+                SS => RegisterSet::SS,
+                DS => RegisterSet::DS,
+                FS => RegisterSet::FS,
+                GS => RegisterSet::GS,
+                _ => RegisterSet::empty(),
+            }
+        } else if reg.is_xmm() || reg.is_ymm() || reg.is_zmm() {
+            match reg.number() {
+                n @ 0..=7 => RegisterSet::from_bits_retain(RegisterSet::XMM0.bits() << n),
+                _ => RegisterSet::empty(),
+            }
+        } else if reg.is_st() || reg.is_mm() {
+            RegisterSet::X87
+        } else {
+            RegisterSet::empty()
         }
     }
 }
@@ -124,6 +316,31 @@ impl From<UsedRegister> for RegisterSet {
             Af => RegisterSet::AF,
             Zf => RegisterSet::ZF,
             Sf => RegisterSet::SF,
+            R8 => RegisterSet::R8,
+            R9 => RegisterSet::R9,
+            R10 => RegisterSet::R10,
+            R11 => RegisterSet::R11,
+            R12 => RegisterSet::R12,
+            R13 => RegisterSet::R13,
+            R14 => RegisterSet::R14,
+            R15 => RegisterSet::R15,
+            Of => RegisterSet::OF,
+            Df => RegisterSet::DF,
+            Xmm0 => RegisterSet::XMM0,
+            Xmm1 => RegisterSet::XMM1,
+            Xmm2 => RegisterSet::XMM2,
+            Xmm3 => RegisterSet::XMM3,
+            Xmm4 => RegisterSet::XMM4,
+            Xmm5 => RegisterSet::XMM5,
+            Xmm6 => RegisterSet::XMM6,
+            Xmm7 => RegisterSet::XMM7,
+            X87 => RegisterSet::X87,
+            Es => RegisterSet::ES,
+            Cs => RegisterSet::CS,
+            Ss => RegisterSet::SS,
+            Ds => RegisterSet::DS,
+            Fs => RegisterSet::FS,
+            Gs => RegisterSet::GS,
         }
     }
 }
@@ -138,36 +355,66 @@ impl Iterator for RegisterSetIterator {
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
+        if self.current >= UsedRegister::LENGTH {
+            return None;
+        }
+        // the sets are sparse, so skip straight to the next set bit
+        let remaining = self.set.bits() >> self.current;
+        if remaining == 0 {
+            return None;
+        }
+        self.current += remaining.trailing_zeros() as usize;
         if self.current >= UsedRegister::LENGTH {
             return None;
         }
         // let current = UsedRegister::from_usize(self.current);
         let current: UsedRegister = unsafe { std::mem::transmute(self.current as u8) };
-        self.current = self.current + 1;
-        if self.set.contains(current.into()) {
-            Some(current)
-        } else {
-            self.next()
-        }
+        self.current += 1;
+        Some(current)
     }
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+/// What the address stored in an [`InstructionFeature`] is
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+enum TargetKind {
+    None,
+    Jump,
+    Memory,
+}
+
+/// The features kept for every superset node, the optional ones are in the side columns of [`SupersetSample`]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct InstructionFeature {
     pub size: u8,
     pub code: Code,
-    pub jump_target: Option<u32>,
     pub falls_through: bool,
+    // the near branches have no memory operand, so a single address is enough
+    target_kind: TargetKind,
+    target: u64,
     pub uses: RegisterSet,
     pub defines: RegisterSet,
+    /// Registers left in an undefined state (like the flags after `mul`), they end the preceding definitions without starting a new one
+    pub kills: RegisterSet,
 }
 
-impl From<iced_x86::Instruction> for InstructionFeature {
-    fn from(instruction: iced_x86::Instruction) -> Self {
-        // TODO: reuse the InstructionInfoFactory (it allocates)
-        let mut factory = InstructionInfoFactory::new();
+impl InstructionFeature {
+    /// Target of a direct near branch
+    ///
+    /// Can be outside of the 32-bit address space in 64-bit mode, such targets never match a superset address
+    pub fn jump_target(&self) -> Option<u64> {
+        (self.target_kind == TargetKind::Jump).then_some(self.target)
+    }
+
+    /// Address of a RIP- (or EIP-) relative memory operand
+    #[allow(unused)]
+    pub fn memory_target(&self) -> Option<u64> {
+        (self.target_kind == TargetKind::Memory).then_some(self.target)
+    }
+
+    /// The `factory` allocates, so it should be reused between the instructions
+    pub fn new(instruction: &iced_x86::Instruction, factory: &mut InstructionInfoFactory) -> Self {
         let instr_info = factory.info_options(
-            &instruction,
+            instruction,
             iced_x86::InstructionInfoOptions::NO_MEMORY_USAGE,
         );
 
@@ -185,7 +432,12 @@ impl From<iced_x86::Instruction> for InstructionFeature {
             })
             .map(|r| r.register().into())
             .fold(RegisterSet::empty(), |acc, v| acc | v)
-            | RegisterSet::from_rflags(instruction.rflags_modified());
+            | RegisterSet::from_rflags(
+                instruction.rflags_written()
+                    | instruction.rflags_cleared()
+                    | instruction.rflags_set(),
+            );
+        let kills = RegisterSet::from_rflags(instruction.rflags_undefined()).difference(defines);
         let uses = instr_info
             .used_registers()
             .iter()
@@ -200,19 +452,27 @@ impl From<iced_x86::Instruction> for InstructionFeature {
             })
             .map(|r| r.register().into())
             .fold(RegisterSet::empty(), |acc, v| acc | v)
-            | RegisterSet::from_rflags(instruction.rflags_read()); // TODO: maybe we want to handle the "undefined" as a special case
+            | RegisterSet::from_rflags(instruction.rflags_read());
+
+        let is_near_branch = instruction.op_kinds().any(|k| {
+            matches!(
+                k,
+                OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+            )
+        });
+        let (target_kind, target) = if is_near_branch {
+            (TargetKind::Jump, instruction.near_branch_target())
+        } else if instruction.is_ip_rel_memory_operand() {
+            (TargetKind::Memory, instruction.ip_rel_memory_address())
+        } else {
+            (TargetKind::None, 0)
+        };
 
         InstructionFeature {
             size: instruction.len() as u8,
             code: instruction.code(),
-            jump_target: if instruction
-                .op_kinds()
-                .contains(&iced_x86::OpKind::NearBranch32)
-            {
-                Some(instruction.near_branch32())
-            } else {
-                None
-            },
+            target_kind,
+            target,
             falls_through: !matches!(
                 instruction.flow_control(),
                 iced_x86::FlowControl::UnconditionalBranch
@@ -222,95 +482,743 @@ impl From<iced_x86::Instruction> for InstructionFeature {
             ),
             defines,
             uses,
+            kills,
         }
     }
 }
 
+/// The most operands an instruction can have
+pub const MAX_OPERANDS: usize = 5;
+
+bitflags! {
+    /// Prefixes of the instruction, as found in its bytes
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct PrefixSet: u16 {
+        const LOCK = 1 << 0;
+        const REP = 1 << 1;
+        const REPNE = 1 << 2;
+        const SEGMENT = 1 << 3;
+        const OPERAND_SIZE = 1 << 4;
+        const ADDRESS_SIZE = 1 << 5;
+        const REX = 1 << 6;
+        const REX_W = 1 << 7;
+    }
+}
+
+impl PrefixSet {
+    fn from_bytes(bytes: &[u8], bitness: u32) -> Self {
+        let mut set = PrefixSet::empty();
+        for &byte in bytes {
+            set |= match byte {
+                0xf0 => PrefixSet::LOCK,
+                0xf3 => PrefixSet::REP,
+                0xf2 => PrefixSet::REPNE,
+                0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 => PrefixSet::SEGMENT,
+                0x66 => PrefixSet::OPERAND_SIZE,
+                0x67 => PrefixSet::ADDRESS_SIZE,
+                // REX is always the last prefix
+                0x40..=0x4f if bitness == 64 => {
+                    if byte & 0x08 != 0 {
+                        set |= PrefixSet::REX_W;
+                    }
+                    set |= PrefixSet::REX;
+                    break;
+                }
+                _ => break,
+            };
+        }
+        set
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct InstructionFlags: u8 {
+        /// Can only be executed in ring 0
+        const PRIVILEGED = 1 << 0;
+        /// Something the compilers virtually never emit
+        const RARE = 1 << 1;
+        /// Implicitly uses the stack (push, pop, call, ret, ...)
+        const STACK = 1 << 2;
+        /// An immediate operand is an address inside of the memory image
+        const IMMEDIATE_IS_ADDRESS = 1 << 3;
+        /// The memory operand displacement (or the RIP-relative target) is an address inside of the memory image
+        const DISPLACEMENT_IS_ADDRESS = 1 << 4;
+    }
+}
+
+fn is_rare_instruction(instruction: &iced_x86::Instruction) -> bool {
+    use Mnemonic::*;
+    matches!(
+        instruction.mnemonic(),
+        In | Insb
+            | Insw
+            | Insd
+            | Out
+            | Outsb
+            | Outsw
+            | Outsd
+            | Aaa
+            | Aad
+            | Aam
+            | Aas
+            | Daa
+            | Das
+            | Into
+            | Bound
+            | Arpl
+            | Lds
+            | Les
+            | Lfs
+            | Lgs
+            | Lss
+            | Salc
+            | Xlatb
+            | Sahf
+            | Lahf
+            | Cmc
+            | Int1
+            | Iret
+            | Iretd
+            | Iretq
+            | Retf
+            | Enter
+            | Wait
+    ) || instruction
+        .op_kinds()
+        .any(|k| matches!(k, OpKind::FarBranch16 | OpKind::FarBranch32))
+}
+
+/// Features that are not needed by the model itself, but can be useful for experimenting
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ExtraFeatures {
+    /// Kinds of the operands, `None` past the operand count
+    pub operand_kinds: [Option<OpKind>; MAX_OPERANDS],
+    pub prefixes: PrefixSet,
+    pub flags: InstructionFlags,
+    /// Base and index registers of the memory operands that are read
+    pub memory_reads: RegisterSet,
+    /// Base and index registers of the memory operands that are written
+    pub memory_writes: RegisterSet,
+    /// How much the instruction changes the stack pointer (e.g. -4 for `push eax`)
+    pub stack_pointer_delta: i32,
+}
+
+impl ExtraFeatures {
+    pub fn new(
+        instruction: &iced_x86::Instruction,
+        bytes: &[u8],
+        memory: &MemoryImage,
+        bitness: u32,
+        factory: &mut InstructionInfoFactory,
+    ) -> Self {
+        let mut operand_kinds = [None; MAX_OPERANDS];
+        for (kind, slot) in instruction.op_kinds().zip(operand_kinds.iter_mut()) {
+            *slot = Some(kind);
+        }
+
+        let mut flags = InstructionFlags::empty();
+        if instruction.is_privileged() {
+            flags |= InstructionFlags::PRIVILEGED;
+        }
+        if is_rare_instruction(instruction) {
+            flags |= InstructionFlags::RARE;
+        }
+        if instruction.is_stack_instruction() {
+            flags |= InstructionFlags::STACK;
+        }
+        if immediate_reference(instruction, memory).is_some() {
+            flags |= InstructionFlags::IMMEDIATE_IS_ADDRESS;
+        }
+        if displacement_reference(instruction, memory).is_some() {
+            flags |= InstructionFlags::DISPLACEMENT_IS_ADDRESS;
+        }
+
+        let mut memory_reads = RegisterSet::empty();
+        let mut memory_writes = RegisterSet::empty();
+        let info = factory.info(instruction);
+        for used_memory in info.used_memory() {
+            let registers =
+                RegisterSet::from(used_memory.base()) | RegisterSet::from(used_memory.index());
+            if matches!(
+                used_memory.access(),
+                OpAccess::Read | OpAccess::CondRead | OpAccess::ReadWrite | OpAccess::ReadCondWrite
+            ) {
+                memory_reads |= registers;
+            }
+            if matches!(
+                used_memory.access(),
+                OpAccess::Write
+                    | OpAccess::CondWrite
+                    | OpAccess::ReadWrite
+                    | OpAccess::ReadCondWrite
+            ) {
+                memory_writes |= registers;
+            }
+        }
+
+        let prefix_len = bytes.len().min(instruction.len());
+        ExtraFeatures {
+            operand_kinds,
+            prefixes: PrefixSet::from_bytes(&bytes[..prefix_len], bitness),
+            flags,
+            memory_reads,
+            memory_writes,
+            stack_pointer_delta: instruction.stack_pointer_increment(),
+        }
+    }
+}
+
+/// Selects the parts of the memory image that become superset nodes
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionFilter {
+    /// Every byte of every region, including the data
+    #[default]
+    All,
+    /// Only the executable regions
+    Executable,
+    /// Only the listed sections of the original executable (the samples without a section map have none)
+    Sections(Vec<String>),
+}
+
+impl RegionFilter {
+    pub fn select(&self, sample: &ExecutableSample) -> IntervalSet<u32> {
+        let region_interval =
+            |item: &MemoryImageItem| Interval::from_start_and_end(item.addr, item.end());
+        match self {
+            RegionFilter::All => sample.memory.iter().map(region_interval).collect(),
+            RegionFilter::Executable => sample
+                .memory
+                .iter()
+                .filter(|item| item.protection.contains(Protection::EXECUTE))
+                .map(region_interval)
+                .collect(),
+            RegionFilter::Sections(names) => sample
+                .sections
+                .iter()
+                .filter(|(_, name)| names.contains(name))
+                .map(|(interval, _)| interval)
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for RegionFilter {
+    type Err = anyhow::Error;
+
+    /// Parses `all`, `executable` or `sections:.text,.plt`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(RegionFilter::All),
+            "executable" => Ok(RegionFilter::Executable),
+            _ => match s.strip_prefix("sections:") {
+                Some(names) => Ok(RegionFilter::Sections(
+                    names.split(',').map(|v| v.to_string()).collect(),
+                )),
+                None => bail!(
+                    "Unknown region filter {}, expected `all`, `executable` or `sections:<name>,...`",
+                    s
+                ),
+            },
+        }
+    }
+}
+
+impl Display for RegionFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegionFilter::All => write!(f, "all"),
+            RegionFilter::Executable => write!(f, "executable"),
+            RegionFilter::Sections(names) => write!(f, "sections:{}", names.join(",")),
+        }
+    }
+}
+
+fn parse_register_classes(input: &str) -> Result<RegisterClasses, bitflags::parser::ParseError> {
+    // bitflags 2.0 only implements `FromStr` for the inner type
+    Ok(RegisterClasses(input.parse()?))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, clap::Args)]
+pub struct SupersetConfig {
+    /// Also compute operand kinds, prefixes, memory accesses and other extra instruction features (slower)
+    #[clap(long)]
+    #[serde(default)]
+    pub extra_features: bool,
+    /// Resolve the jump tables and the pointers used by the indirect jumps and calls
+    #[clap(long)]
+    #[serde(default)]
+    pub jump_tables: bool,
+    /// Propagate the results of the PC thunks to resolve the GOT-relative references in the i386 PIC code
+    #[clap(long)]
+    #[serde(default)]
+    pub resolve_pic: bool,
+    /// Take the immediates and displacements pointing into the image as data references
+    #[clap(long)]
+    #[serde(default)]
+    pub data_references: bool,
+    /// Follow the values spilled to the `[esp+x]` and `[ebp+x]` stack slots to add the store to load dependencies
+    #[clap(long)]
+    #[serde(default)]
+    pub stack_dependencies: bool,
+    /// Registers to track for the data dependencies, e.g. "GPR | FLAGS | SSE | X87 | SEGMENT"
+    #[clap(long, value_parser = parse_register_classes, default_value = "GPR | FLAGS")]
+    #[serde(default)]
+    pub register_classes: RegisterClasses,
+    /// Which bytes become superset nodes: `all`, `executable` or `sections:<name>,...`
+    #[clap(long, default_value = "all")]
+    #[serde(default)]
+    pub regions: RegionFilter,
+}
+
+impl SupersetConfig {
+    /// Whether [`SupersetSample::references`] are looked for
+    pub fn has_references(&self) -> bool {
+        self.data_references || self.resolve_pic
+    }
+}
+
+/// The superset disassembly, stored as parallel arrays with an entry per decoded address
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SupersetSample {
-    pub superset: Vec<(u32, InstructionFeature, Option<Label>)>,
+    pub addresses: Vec<u32>,
+    pub instructions: Vec<InstructionFeature>,
+    pub labels: Vec<Option<Label>>,
+    /// Whether the node is an entry point of a function
+    pub is_function_start: Vec<bool>,
+    /// Whether the node is inside a resolved jump table, so it's likely data
+    pub data_hints: Vec<bool>,
+    /// The byte at the node address
+    pub bytes: Vec<u8>,
+    /// The [`Protection`] bits of the memory region the node is in
+    pub protections: Vec<u8>,
+    /// Only computed if requested by [`SupersetConfig::extra_features`], otherwise empty
+    pub extra: Vec<ExtraFeatures>,
+    /// Only computed if requested by [`SupersetConfig::stack_dependencies`] (and not for the 16-bit samples), otherwise empty
+    pub stack_effects: Vec<StackEffect>,
+    /// Only resolved if requested by [`SupersetConfig::jump_tables`], sorted by the source address
+    pub jump_tables: Vec<JumpTable>,
+    /// Only found if requested by [`SupersetConfig::data_references`] or [`SupersetConfig::resolve_pic`], sorted by the source address
+    pub references: Vec<Reference>,
+    /// The config the superset was made with, it's written along with the exported data
+    pub config: SupersetConfig,
 }
 
-impl SupersetSample {
-    pub fn new(sample: ExecutableSample) -> Self {
-        let mut instruction_addresses = HashSet::new();
-
-        // the assumption here is that inside the interval marked as code there is no gaps
-        // this __should__ be true if the compiler is sane
-        for interval in sample.classes.true_instructions.iter() {
-            let mut decoder = iced_x86::Decoder::new(
-                32,
-                &sample.memory.execute_all_at(interval.start())[..interval.len() as usize],
-                DecoderOptions::NONE,
-            );
-            decoder.set_ip(interval.start() as u64);
+/// Whether the instruction is something a compiler or an assembler would use to pad the code
+fn is_padding_instruction(instruction: &iced_x86::Instruction) -> bool {
+    match instruction.mnemonic() {
+        Mnemonic::Nop | Mnemonic::Int3 => true,
+        // mov edi, edi
+        Mnemonic::Mov => {
+            instruction.op_count() == 2
+                && instruction.op0_kind() == OpKind::Register
+                && instruction.op1_kind() == OpKind::Register
+                && instruction.op0_register() == instruction.op1_register()
+        }
+        // lea esi, [esi+0] and lea esi, [esi+eiz*1+0]
+        Mnemonic::Lea => {
+            instruction.op0_register() == instruction.memory_base()
+                && instruction.memory_index() == iced_x86::Register::None
+                && instruction.memory_displacement32() == 0
+        }
+        _ => false,
+    }
+}
 
-            loop {
-                let instr = decoder.decode();
-                if instr.is_invalid() {
-                    break;
-                }
-                instruction_addresses.insert(instr.ip32());
+/// Compute the labels for every byte of the memory region
+fn label_region(item: &MemoryImageItem, classes: &AddressClasses, bitness: u32) -> Vec<Label> {
+    let region = Interval::from_start_and_end(item.addr, item.end());
+    let mut labels = vec![Label::Unknown; item.data.len()];
+
+    for interval in classes.true_data.overlapping(region) {
+        let interval = interval.intersection(region);
+        for address in interval.start()..interval.end() {
+            labels[(address - item.addr) as usize] = Label::Data;
+        }
+    }
+
+    // the assumption here is that inside the interval marked as code there is no gaps
+    // this __should__ be true if the compiler is sane
+    for interval in classes.true_instructions.overlapping(region) {
+        let interval = interval.intersection(region);
+        let start = (interval.start() - item.addr) as usize;
+        let end = (interval.end() - item.addr) as usize;
+        let mut decoder =
+            iced_x86::Decoder::new(bitness, &item.data[start..end], DecoderOptions::NONE);
+        decoder.set_ip(interval.start() as u64);
+
+        loop {
+            let instr = decoder.decode();
+            if instr.is_invalid() {
+                break;
             }
+            let offset = (instr.ip32() - item.addr) as usize;
+            labels[offset] = Label::InstructionStart;
+            labels[offset + 1..offset + instr.len()].fill(Label::InstructionBody);
         }
+    }
 
-        let mut superset = Vec::new();
-        for item in sample.memory.iter() {
-            let mut decoder = iced_x86::Decoder::new(32, &item.data, 0);
-
-            for address in item.addr..item.end() {
-                decoder
-                    .set_position((address - item.addr) as usize)
-                    .unwrap();
-                decoder.set_ip(address as u64);
-                let instruction = decoder.decode();
-                let instruction = InstructionFeature::from(instruction);
-
-                let label = Some(if instruction_addresses.contains(&address) {
-                    Label::Code
-                } else {
-                    Label::NotCode
-                });
-                superset.push((address, instruction, label));
+    // padding can only be found in the gaps between the labeled bytes of the executable regions
+    if item.protection.contains(Protection::EXECUTE) {
+        let labeled = classes
+            .true_instructions
+            .overlapping(region)
+            .chain(classes.true_data.overlapping(region))
+            .collect::<IntervalSet<_>>();
+        for gap in labeled.complement(region).iter() {
+            let start = (gap.start() - item.addr) as usize;
+            let end = (gap.end() - item.addr) as usize;
+
+            let mut decoder =
+                iced_x86::Decoder::new(bitness, &item.data[start..end], DecoderOptions::NONE);
+            if decoder.iter().all(|instr| is_padding_instruction(&instr)) {
+                labels[start..end].fill(Label::Padding);
             }
         }
+    }
+
+    labels
+}
+
+/// The decoding is split into chunks of this many addresses, so that a single big region can be decoded in parallel
+const CHUNK_SIZE: u32 = 0x10000;
+
+/// A part of the superset, decoded by a single task
+struct SupersetChunk {
+    addresses: Vec<u32>,
+    instructions: Vec<InstructionFeature>,
+    labels: Vec<Option<Label>>,
+    is_function_start: Vec<bool>,
+    bytes: Vec<u8>,
+    protections: Vec<u8>,
+    extra: Vec<ExtraFeatures>,
+    stack_effects: Vec<StackEffect>,
+    jump_tables: Vec<JumpTable>,
+    references: Vec<Reference>,
+}
 
-        SupersetSample { superset }
+impl SupersetChunk {
+    /// Only reserves the optional columns that `decoder` fills
+    fn with_capacity(capacity: usize, decoder: &ChunkDecoder) -> Self {
+        let optional_capacity = |enabled: bool| if enabled { capacity } else { 0 };
+        Self {
+            addresses: Vec::with_capacity(capacity),
+            instructions: Vec::with_capacity(capacity),
+            labels: Vec::with_capacity(capacity),
+            is_function_start: Vec::with_capacity(capacity),
+            bytes: Vec::with_capacity(capacity),
+            protections: Vec::with_capacity(capacity),
+            extra: Vec::with_capacity(optional_capacity(decoder.config.extra_features)),
+            stack_effects: Vec::with_capacity(optional_capacity(decoder.has_stack_effects())),
+            jump_tables: Vec::new(),
+            references: Vec::new(),
+        }
+    }
+
+    fn append(&mut self, other: Self) {
+        self.addresses.extend(other.addresses);
+        self.instructions.extend(other.instructions);
+        self.labels.extend(other.labels);
+        self.is_function_start.extend(other.is_function_start);
+        self.bytes.extend(other.bytes);
+        self.protections.extend(other.protections);
+        self.extra.extend(other.extra);
+        self.stack_effects.extend(other.stack_effects);
+        self.jump_tables.extend(other.jump_tables);
+        self.references.extend(other.references);
     }
 
-    pub fn into_graph(self) -> GraphSample {
-        GraphSample::new(self)
+    /// Adds the parts that need to see every decoded address
+    fn into_superset(self, sample: &ExecutableSample, config: &SupersetConfig) -> SupersetSample {
+        let tables = self
+            .jump_tables
+            .iter()
+            .map(|v| v.table)
+            .collect::<IntervalSet<_>>();
+        let data_hints = self
+            .addresses
+            .par_iter()
+            .map(|&address| tables.contains(address))
+            .collect();
+
+        let mut references = self.references;
+        if config.resolve_pic && sample.architecture.bitness() == 32 {
+            references.extend(resolve_pic_references(
+                &self.addresses,
+                &self.instructions,
+                &sample.memory,
+            ));
+            references.sort_by_key(|v| v.source);
+        }
+
+        SupersetSample {
+            addresses: self.addresses,
+            instructions: self.instructions,
+            labels: self.labels,
+            is_function_start: self.is_function_start,
+            data_hints,
+            bytes: self.bytes,
+            protections: self.protections,
+            extra: self.extra,
+            stack_effects: self.stack_effects,
+            jump_tables: self.jump_tables,
+            references,
+            config: config.clone(),
+        }
     }
+}
+
+/// Decodes the addresses of a sample into chunks
+struct ChunkDecoder<'a> {
+    sample: &'a ExecutableSample,
+    config: &'a SupersetConfig,
+    bitness: u32,
+    tracked_registers: RegisterSet,
+    got: Option<u32>,
+}
+
+impl<'a> ChunkDecoder<'a> {
+    fn new(sample: &'a ExecutableSample, config: &'a SupersetConfig) -> Self {
+        Self {
+            sample,
+            config,
+            bitness: sample.architecture.bitness(),
+            tracked_registers: config.register_classes.registers(),
+            got: global_offset_table(sample),
+        }
+    }
+
+    fn has_stack_effects(&self) -> bool {
+        self.config.stack_dependencies && self.bitness != 16
+    }
+
+    /// Decode every address of the range, the labels are the ones of the whole region
+    fn decode(
+        &self,
+        item: &MemoryImageItem,
+        labels: &[Label],
+        range: Interval<u32>,
+        factory: &mut InstructionInfoFactory,
+    ) -> SupersetChunk {
+        let (sample, config, bitness) = (self.sample, self.config, self.bitness);
+        let mut result = SupersetChunk::with_capacity(range.len() as usize, self);
+        let mut decoder = iced_x86::Decoder::new(bitness, &item.data, 0);
+
+        for address in range.start()..range.end() {
+            let offset = (address - item.addr) as usize;
+            decoder.set_position(offset).unwrap();
+            decoder.set_ip(address as u64);
+            let instruction = decoder.decode();
+            let mut feature = InstructionFeature::new(&instruction, factory);
+            feature.uses &= self.tracked_registers;
+            feature.defines &= self.tracked_registers;
+            feature.kills &= self.tracked_registers;
+            if config.extra_features {
+                result.extra.push(ExtraFeatures::new(
+                    &instruction,
+                    &item.data[offset..],
+                    &sample.memory,
+                    bitness,
+                    factory,
+                ));
+            }
+            if self.has_stack_effects() {
+                result
+                    .stack_effects
+                    .push(StackEffect::new(&instruction, bitness, factory));
+            }
+            if config.jump_tables {
+                result.jump_tables.extend(resolve_jump_table(
+                    &instruction,
+                    &sample.memory,
+                    bitness,
+                    self.got,
+                ));
+            }
+            if config.data_references {
+                result
+                    .references
+                    .extend(operand_references(&instruction, &sample.memory));
+            }
+
+            result.addresses.push(address);
+            result.instructions.push(feature);
+            result.labels.push(Some(labels[offset]));
+            result
+                .is_function_start
+                .push(sample.classes.function_starts.contains(&address));
+            result.bytes.push(item.data[offset]);
+            result.protections.push(item.protection.bits() as u8);
+        }
 
-    pub fn to_parquet<W: Write>(self, writer: W) -> anyhow::Result<()> {
+        result
+    }
+}
+
+impl SupersetSample {
+    pub fn new(sample: ExecutableSample) -> Self {
+        Self::with_config(&sample, &SupersetConfig::default())
+    }
+
+    pub fn with_config(sample: &ExecutableSample, config: &SupersetConfig) -> Self {
+        let decoder = ChunkDecoder::new(sample, config);
+        let selected = config.regions.select(sample);
+
+        let regions = sample
+            .memory
+            .iter()
+            .filter_map(|item| {
+                let region = Interval::from_start_and_end(item.addr, item.end());
+                let ranges = selected
+                    .overlapping(region)
+                    .map(|v| v.intersection(region))
+                    .collect::<Vec<_>>();
+                (!ranges.is_empty()).then_some((item, ranges))
+            })
+            .collect::<Vec<_>>();
+        // the labels depend on the whole region, so they are computed before splitting it into chunks
+        let labels = regions
+            .par_iter()
+            .map(|(item, _)| label_region(item, &sample.classes, decoder.bitness))
+            .collect::<Vec<_>>();
+        let chunks = regions
+            .iter()
+            .zip(labels.iter())
+            .flat_map(|((item, ranges), labels)| {
+                ranges.iter().flat_map(move |range| {
+                    (range.start()..range.end())
+                        .step_by(CHUNK_SIZE as usize)
+                        .map(move |start| {
+                            let end = start.saturating_add(CHUNK_SIZE).min(range.end());
+                            (*item, labels, Interval::from_start_and_end(start, end))
+                        })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let len = chunks
+            .iter()
+            .map(|(_, _, chunk)| chunk.len() as usize)
+            .sum();
+        let mut result = SupersetChunk::with_capacity(len, &decoder);
+        // only a batch of chunks is decoded at a time and appended right away, so the decoded
+        // superset isn't held twice
+        let batch_size = rayon::current_num_threads() * 2;
+        for batch in chunks.chunks(batch_size) {
+            let decoded = batch
+                .par_iter()
+                .map_init(
+                    InstructionInfoFactory::new,
+                    |factory, &(item, labels, chunk)| decoder.decode(item, labels, chunk, factory),
+                )
+                .collect::<Vec<_>>();
+            for chunk in decoded {
+                result.append(chunk);
+            }
+        }
+
+        result.into_superset(sample, config)
+    }
+
+    pub fn into_graph(self, config: &GraphConfig) -> GraphSample {
+        GraphSample::new(self, config)
+    }
+
+    /// `sample_hash` is the [`ExecutableSample::hash`] of the sample the superset was made from
+    pub fn to_parquet<W: Write>(self, sample_hash: &str, writer: W) -> anyhow::Result<()> {
         #[derive(ParquetRecordWriter)]
         struct Record {
             pub addr: i32,
             pub size: i32,
             pub code: i32,
+            /// Whether the address is an instruction start
             pub label: Option<bool>,
+            /// The [`Label`] as an integer
+            pub label_class: Option<i32>,
+            pub function_start: bool,
+            /// Only present if the jump tables were resolved
+            pub data_hint: Option<bool>,
+            /// An address referenced by the instruction, only present if the references were looked for
+            pub reference: Option<i64>,
+            // the rest is only present if the extra features were computed
+            pub op0_kind: Option<i32>,
+            pub op1_kind: Option<i32>,
+            pub op2_kind: Option<i32>,
+            pub op3_kind: Option<i32>,
+            pub op4_kind: Option<i32>,
+            pub prefixes: Option<i32>,
+            pub flags: Option<i32>,
+            pub memory_reads: Option<i64>,
+            pub memory_writes: Option<i64>,
+            pub stack_pointer_delta: Option<i32>,
         }
 
-        let records = self
-            .superset
+        let metadata = vec![
+            KeyValue::new(
+                "superset_config".to_string(),
+                serde_json::to_string(&self.config)?,
+            ),
+            KeyValue::new("sample_hash".to_string(), sample_hash.to_string()),
+        ];
+
+        let jump_tables = self.config.jump_tables;
+        let has_references = self.config.has_references();
+        let references = self
+            .references
+            .iter()
+            .map(|r| (r.source, r.target))
+            .collect::<FxHashMap<_, _>>();
+        // the extra features are either missing or present for every address
+        let extra = self
+            .extra
             .into_iter()
-            .map(|(addr, instr, label)| Record {
+            .map(Some)
+            .chain(std::iter::repeat(None));
+        let records = itertools::izip!(
+            self.addresses,
+            self.instructions,
+            self.labels,
+            self.is_function_start,
+            self.data_hints,
+            extra
+        )
+        .map(|(addr, instr, label, function_start, data_hint, extra)| {
+            let extra = extra.as_ref();
+            let op_kind = |i: usize| {
+                extra
+                    .and_then(|e| e.operand_kinds[i])
+                    .map(|k| k as u8 as i32)
+            };
+            Record {
                 addr: addr.try_into().unwrap(),
                 size: instr.size as i32,
                 code: instr.code as u16 as i32,
-                label: label.map(|v| match v {
-                    Label::Code => true,
-                    Label::NotCode => false,
-                }),
-            })
-            .collect::<Vec<_>>();
+                label: label.map(|v| v.is_code()),
+                label_class: label.map(|v| v as u8 as i32),
+                function_start,
+                data_hint: jump_tables.then_some(data_hint),
+                reference: has_references.then(|| references.get(&addr).map_or(-1, |&v| v as i64)),
+                op0_kind: op_kind(0),
+                op1_kind: op_kind(1),
+                op2_kind: op_kind(2),
+                op3_kind: op_kind(3),
+                op4_kind: op_kind(4),
+                prefixes: extra.map(|e| e.prefixes.bits() as i32),
+                flags: extra.map(|e| e.flags.bits() as i32),
+                memory_reads: extra.map(|e| e.memory_reads.bits() as i64),
+                memory_writes: extra.map(|e| e.memory_writes.bits() as i64),
+                stack_pointer_delta: extra.map(|e| e.stack_pointer_delta),
+            }
+        })
+        .collect::<Vec<_>>();
 
         let records = records.as_slice();
 
         let schema = records.schema()?;
         let props = Arc::new(
             WriterProperties::builder()
-                // .set_key_value_metadata(Some(metadata))
+                .set_key_value_metadata(Some(metadata))
                 .set_compression(Compression::ZSTD)
                 .build(),
         );
@@ -325,3 +1233,441 @@ impl SupersetSample {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::interval_set::IntervalMap;
+    use crate::model::Architecture;
+    use memory_image::MemoryImage;
+
+    #[test]
+    fn test_register_tracking() {
+        #[rustfmt::skip]
+        let code = vec![
+            0xf7, 0xe1,                   // mul ecx
+            0x0f, 0x58, 0xc1,             // addps xmm0, xmm1
+            0xd8, 0xc1,                   // fadd st(0), st(1)
+            0x8e, 0xd8,                   // mov ds, eax
+            0xfd,                         // std
+        ];
+        let feature = |code: &[u8], classes| {
+            let mut memory = MemoryImage::new();
+            memory.add_region(0, Protection::READ_EXECUTE, code.to_vec(), "".to_string());
+            let sample = ExecutableSample::new(memory, AddressClasses::new()).unwrap();
+            let config = SupersetConfig {
+                register_classes: classes,
+                ..Default::default()
+            };
+            SupersetSample::with_config(&sample, &config).instructions
+        };
+
+        let all = RegisterClasses::all();
+        let superset = feature(&code, all);
+
+        let mul = superset[0];
+        assert!(mul.defines.contains(RegisterSet::CF | RegisterSet::OF));
+        assert!(mul
+            .kills
+            .contains(RegisterSet::ZF | RegisterSet::SF | RegisterSet::PF | RegisterSet::AF));
+        assert!(!mul.defines.intersects(mul.kills));
+
+        let addps = superset[2];
+        assert!(addps.uses.contains(RegisterSet::XMM0 | RegisterSet::XMM1));
+        assert!(addps.defines.contains(RegisterSet::XMM0));
+
+        let fadd = superset[5];
+        assert!(fadd.uses.contains(RegisterSet::X87));
+        assert!(fadd.defines.contains(RegisterSet::X87));
+
+        let mov = superset[7];
+        assert!(mov.defines.contains(RegisterSet::DS));
+
+        let std = superset[9];
+        assert!(std.defines.contains(RegisterSet::DF));
+
+        // with the default classes only the GPRs and the flags are left
+        let superset = feature(&code, RegisterClasses::default());
+        let addps = superset[2];
+        assert!(addps.uses.is_empty());
+        let mov = superset[7];
+        assert!(mov.defines.is_empty());
+        assert!(mov.uses.contains(RegisterSet::EAX));
+    }
+
+    #[test]
+    fn test_extra_features() {
+        #[rustfmt::skip]
+        let code = vec![
+            0x68, 0x00, 0x10, 0x00, 0x00, // push 0x1000
+            0xf0, 0x01, 0x03,             // lock add [ebx], eax
+            0xa1, 0x00, 0x10, 0x00, 0x00, // mov eax, [0x1000]
+            0xe4, 0x60,                   // in al, 0x60
+            0xf4,                         // hlt
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        let sample = ExecutableSample::new(memory, AddressClasses::new()).unwrap();
+
+        let superset = SupersetSample::with_config(
+            &sample,
+            &SupersetConfig {
+                extra_features: true,
+                ..Default::default()
+            },
+        )
+        .extra;
+        let extra = |offset: usize| superset[offset];
+
+        let push = extra(0);
+        assert_eq!(
+            push.operand_kinds,
+            [Some(OpKind::Immediate32), None, None, None, None]
+        );
+        assert!(push
+            .flags
+            .contains(InstructionFlags::STACK | InstructionFlags::IMMEDIATE_IS_ADDRESS));
+        assert_eq!(push.stack_pointer_delta, -4);
+
+        let add = extra(5);
+        assert!(add.prefixes.contains(PrefixSet::LOCK));
+        assert!(add.memory_reads.contains(RegisterSet::EBX));
+        assert!(add.memory_writes.contains(RegisterSet::EBX));
+        assert!(!add.memory_writes.contains(RegisterSet::EAX));
+
+        let mov = extra(8);
+        assert!(mov
+            .flags
+            .contains(InstructionFlags::DISPLACEMENT_IS_ADDRESS));
+        assert!(mov.prefixes.is_empty());
+
+        assert!(extra(13).flags.contains(InstructionFlags::RARE));
+        assert!(extra(15).flags.contains(InstructionFlags::PRIVILEGED));
+    }
+
+    #[test]
+    fn test_feature_size() {
+        // kept for every byte of the image, the optional features are in the side columns
+        assert!(std::mem::size_of::<InstructionFeature>() <= 40);
+    }
+
+    #[test]
+    fn test_64bit_features() {
+        #[rustfmt::skip]
+        let code = vec![
+            0x4c, 0x8b, 0x05, 0x10, 0x00, 0x00, 0x00, // mov r8, [rip+0x10]
+            0x49, 0x01, 0xc1,                         // add r9, rax
+            0xeb, 0xf4,                               // jmp 0x1000
+        ];
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        let sample = ExecutableSample::new(memory, AddressClasses::new())
+            .unwrap()
+            .with_architecture(Architecture::X86_64);
+
+        let superset = SupersetSample::new(sample).instructions;
+
+        let mov = superset[0];
+        assert_eq!(mov.size, 7);
+        assert_eq!(mov.memory_target(), Some(0x1017));
+        assert_eq!(mov.jump_target(), None);
+        assert!(mov.defines.contains(RegisterSet::R8));
+
+        let add = superset[7];
+        assert!(add.uses.contains(RegisterSet::R9 | RegisterSet::EAX));
+        assert!(add.defines.contains(RegisterSet::R9 | RegisterSet::CF));
+
+        let jmp = superset[10];
+        assert_eq!(jmp.jump_target(), Some(0x1000));
+        assert_eq!(jmp.memory_target(), None);
+        assert!(!jmp.falls_through);
+    }
+
+    /// The per-address loop the superset was made with before the parallel decoding, kept as a reference
+    fn sequential_superset(sample: &ExecutableSample, config: &SupersetConfig) -> SupersetSample {
+        let bitness = sample.architecture.bitness();
+        let tracked_registers = config.register_classes.registers();
+        let got = global_offset_table(sample);
+        let selected = config.regions.select(sample);
+        let mut factory = InstructionInfoFactory::new();
+        let mut superset = SupersetSample {
+            addresses: Vec::new(),
+            instructions: Vec::new(),
+            labels: Vec::new(),
+            is_function_start: Vec::new(),
+            data_hints: Vec::new(),
+            bytes: Vec::new(),
+            protections: Vec::new(),
+            extra: Vec::new(),
+            stack_effects: Vec::new(),
+            jump_tables: Vec::new(),
+            references: Vec::new(),
+            config: config.clone(),
+        };
+
+        for item in sample.memory.iter() {
+            let region = Interval::from_start_and_end(item.addr, item.end());
+            let labels = label_region(item, &sample.classes, bitness);
+            let mut decoder = iced_x86::Decoder::new(bitness, &item.data, 0);
+            for range in selected.overlapping(region) {
+                let range = range.intersection(region);
+                for address in range.start()..range.end() {
+                    let offset = (address - item.addr) as usize;
+                    decoder.set_position(offset).unwrap();
+                    decoder.set_ip(address as u64);
+                    let instruction = decoder.decode();
+
+                    let mut feature = InstructionFeature::new(&instruction, &mut factory);
+                    feature.uses &= tracked_registers;
+                    feature.defines &= tracked_registers;
+                    feature.kills &= tracked_registers;
+                    superset.addresses.push(address);
+                    superset.instructions.push(feature);
+                    superset.labels.push(Some(labels[offset]));
+                    superset
+                        .is_function_start
+                        .push(sample.classes.function_starts.contains(&address));
+                    superset.bytes.push(item.data[offset]);
+                    superset.protections.push(item.protection.bits() as u8);
+                    if config.extra_features {
+                        superset.extra.push(ExtraFeatures::new(
+                            &instruction,
+                            &item.data[offset..],
+                            &sample.memory,
+                            bitness,
+                            &mut factory,
+                        ));
+                    }
+                    if config.stack_dependencies && bitness != 16 {
+                        superset.stack_effects.push(StackEffect::new(
+                            &instruction,
+                            bitness,
+                            &mut factory,
+                        ));
+                    }
+                    if config.jump_tables {
+                        superset.jump_tables.extend(resolve_jump_table(
+                            &instruction,
+                            &sample.memory,
+                            bitness,
+                            got,
+                        ));
+                    }
+                    if config.data_references {
+                        superset
+                            .references
+                            .extend(operand_references(&instruction, &sample.memory));
+                    }
+                }
+            }
+        }
+
+        let tables = superset
+            .jump_tables
+            .iter()
+            .map(|v| v.table)
+            .collect::<IntervalSet<_>>();
+        superset.data_hints = superset
+            .addresses
+            .iter()
+            .map(|&address| tables.contains(address))
+            .collect();
+        if config.resolve_pic && bitness == 32 {
+            let references =
+                resolve_pic_references(&superset.addresses, &superset.instructions, &sample.memory);
+            superset.references.extend(references);
+            superset.references.sort_by_key(|v| v.source);
+        }
+
+        superset
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        // pseudo-random bytes, with the first region spanning several chunks
+        let mut state = 0x12345678u32;
+        let mut bytes = |len: usize| {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                    (state >> 16) as u8
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut memory = MemoryImage::new();
+        memory.add_region(
+            0x10000,
+            Protection::READ_EXECUTE,
+            bytes(CHUNK_SIZE as usize * 2 + 0x123),
+            "".to_string(),
+        );
+        memory.add_region(0x40000, Protection::READ, bytes(0x100), "".to_string());
+        memory.add_region(
+            0x50000,
+            Protection::READ_EXECUTE,
+            bytes(0x80),
+            "".to_string(),
+        );
+        let mut classes = AddressClasses::new();
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0x10000, 0x10100));
+        classes
+            .true_data
+            .push(Interval::from_start_and_end(0x40000, 0x40100));
+        classes.function_starts.insert(0x10000);
+        classes.function_starts.insert(0x50010);
+        let sample = ExecutableSample::new(memory, classes).unwrap();
+        let config = SupersetConfig {
+            extra_features: true,
+            register_classes: RegisterClasses::all(),
+            stack_dependencies: true,
+            jump_tables: true,
+            data_references: true,
+            resolve_pic: true,
+            ..Default::default()
+        };
+
+        let SupersetSample {
+            addresses,
+            instructions,
+            labels,
+            is_function_start,
+            data_hints,
+            bytes,
+            protections,
+            extra,
+            stack_effects,
+            jump_tables,
+            references,
+            config: actual_config,
+        } = SupersetSample::with_config(&sample, &config);
+        let expected = sequential_superset(&sample, &config);
+        assert_eq!(
+            addresses.len(),
+            2 * CHUNK_SIZE as usize + 0x123 + 0x100 + 0x80
+        );
+        assert_eq!(addresses, expected.addresses);
+        assert!(instructions == expected.instructions);
+        assert_eq!(labels, expected.labels);
+        assert_eq!(is_function_start, expected.is_function_start);
+        assert_eq!(data_hints, expected.data_hints);
+        assert_eq!(bytes, expected.bytes);
+        assert_eq!(protections, expected.protections);
+        assert_eq!(extra.len(), addresses.len());
+        assert!(extra == expected.extra);
+        assert!(stack_effects == expected.stack_effects);
+        assert_eq!(jump_tables, expected.jump_tables);
+        assert_eq!(references, expected.references);
+        assert_eq!(actual_config, expected.config);
+    }
+
+    #[test]
+    fn test_region_filter() {
+        let mut memory = MemoryImage::new();
+        memory.add_region(
+            0x1000,
+            Protection::READ_EXECUTE,
+            vec![0x90; 4],
+            "".to_string(),
+        );
+        memory.add_region(0x2000, Protection::READ, vec![0; 2], "".to_string());
+        let mut sections = IntervalMap::new();
+        sections.insert(
+            Interval::from_start_and_end(0x1000, 0x1002),
+            ".init".to_string(),
+        );
+        sections.insert(
+            Interval::from_start_and_end(0x1002, 0x1004),
+            ".text".to_string(),
+        );
+        sections.insert(
+            Interval::from_start_and_end(0x2000, 0x2002),
+            ".rodata".to_string(),
+        );
+        let sample = ExecutableSample::new(memory, AddressClasses::new())
+            .unwrap()
+            .with_sections(sections);
+
+        let addresses = |regions: &str| {
+            let config = SupersetConfig {
+                regions: regions.parse().unwrap(),
+                ..Default::default()
+            };
+            SupersetSample::with_config(&sample, &config).addresses
+        };
+
+        assert_eq!(
+            addresses("all"),
+            vec![0x1000, 0x1001, 0x1002, 0x1003, 0x2000, 0x2001]
+        );
+        assert_eq!(
+            addresses("executable"),
+            vec![0x1000, 0x1001, 0x1002, 0x1003]
+        );
+        assert_eq!(
+            addresses("sections:.text,.rodata"),
+            vec![0x1002, 0x1003, 0x2000, 0x2001]
+        );
+        assert_eq!(addresses("sections:.bss"), Vec::<u32>::new());
+
+        assert!("text".parse::<RegionFilter>().is_err());
+        assert_eq!(
+            "sections:.text,.plt"
+                .parse::<RegionFilter>()
+                .unwrap()
+                .to_string(),
+            "sections:.text,.plt"
+        );
+    }
+
+    #[test]
+    fn test_label_region() {
+        #[rustfmt::skip]
+        let code = vec![
+            0x55,             // push ebp
+            0x89, 0xe5,       // mov ebp, esp
+            0xc3,             // ret
+            0xcc, 0xcc,       // int3; int3
+            0x8d, 0x76, 0x00, // lea esi, [esi+0]
+            0x90,             // nop
+            0x01, 0x02,       // data
+            0x00, 0x00,       // unlabeled
+            0xc3,             // ret
+        ];
+        let item = MemoryImageItem::new(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+
+        let mut classes = AddressClasses::new();
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0x1000, 0x1004));
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0x100e, 0x100f));
+        classes
+            .true_data
+            .push(Interval::from_start_and_end(0x100a, 0x100c));
+
+        use Label::*;
+        assert_eq!(
+            label_region(&item, &classes, 32),
+            vec![
+                InstructionStart,
+                InstructionStart,
+                InstructionBody,
+                InstructionStart,
+                Padding,
+                Padding,
+                Padding,
+                Padding,
+                Padding,
+                Padding,
+                Data,
+                Data,
+                Unknown,
+                Unknown,
+                InstructionStart,
+            ]
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Consistency checks of the ground truth
+//!
+//! The rest of the pipeline assumes that the code intervals are made of whole instructions,
+//! don't overlap with data and are executable. The loaders don't guarantee any of that,
+//! so here we check it after the fact.
+
+use crate::model::interval_set::{Interval, IntervalSet};
+use crate::model::ExecutableSample;
+use iced_x86::{DecoderOptions, OpKind};
+use memory_image::Protection;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationIssue {
+    /// Bytes marked both as code and as data
+    CodeDataOverlap { start: u32, end: u32 },
+    /// Bytes marked as code, but not in an executable region
+    CodeNotExecutable { start: u32, end: u32 },
+    /// Linear decoding of the code interval (or of its part in one memory region) ran into an invalid instruction
+    InvalidInstruction {
+        interval_start: u32,
+        interval_end: u32,
+        address: u32,
+    },
+    /// The last instruction of the code interval (or of its part in one memory region) goes past its end
+    DecodingOverrun {
+        interval_start: u32,
+        interval_end: u32,
+        instruction_end: u32,
+    },
+    /// A true instruction jumps into the middle of another true instruction
+    JumpIntoInstruction { source: u32, target: u32 },
+}
+
+impl ValidationIssue {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ValidationIssue::CodeDataOverlap { .. } => "code_data_overlap",
+            ValidationIssue::CodeNotExecutable { .. } => "code_not_executable",
+            ValidationIssue::InvalidInstruction { .. } => "invalid_instruction",
+            ValidationIssue::DecodingOverrun { .. } => "decoding_overrun",
+            ValidationIssue::JumpIntoInstruction { .. } => "jump_into_instruction",
+        }
+    }
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            ValidationIssue::CodeDataOverlap { start, end } => {
+                write!(f, "0x{:08x} - 0x{:08x} is both code and data", start, end)
+            }
+            ValidationIssue::CodeNotExecutable { start, end } => write!(
+                f,
+                "0x{:08x} - 0x{:08x} is code, but not in an executable region",
+                start, end
+            ),
+            ValidationIssue::InvalidInstruction {
+                interval_start,
+                interval_end,
+                address,
+            } => write!(
+                f,
+                "0x{:08x} - 0x{:08x} has an invalid instruction at 0x{:08x}",
+                interval_start, interval_end, address
+            ),
+            ValidationIssue::DecodingOverrun {
+                interval_start,
+                interval_end,
+                instruction_end,
+            } => write!(
+                f,
+                "0x{:08x} - 0x{:08x} ends in the middle of an instruction ending at 0x{:08x}",
+                interval_start, interval_end, instruction_end
+            ),
+            ValidationIssue::JumpIntoInstruction { source, target } => write!(
+                f,
+                "0x{:08x} jumps to 0x{:08x}, which is inside of an instruction",
+                source, target
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+pub fn validate_sample(sample: &ExecutableSample) -> ValidationReport {
+    let mut issues = Vec::new();
+    let classes = &sample.classes;
+
+    for overlap in classes
+        .true_instructions
+        .intersection(&classes.true_data)
+        .iter()
+    {
+        issues.push(ValidationIssue::CodeDataOverlap {
+            start: overlap.start(),
+            end: overlap.end(),
+        });
+    }
+
+    let executable = sample
+        .memory
+        .iter()
+        .filter(|item| item.protection.contains(Protection::EXECUTE))
+        .map(|item| Interval::from_start_and_end(item.addr, item.end()))
+        .collect::<IntervalSet<_>>();
+    let not_executable = classes.true_instructions.difference(&executable);
+    for interval in not_executable.iter() {
+        issues.push(ValidationIssue::CodeNotExecutable {
+            start: interval.start(),
+            end: interval.end(),
+        });
+    }
+
+    let mut instruction_starts = BTreeSet::new();
+    let mut jumps = Vec::new();
+
+    for interval in classes.true_instructions.iter() {
+        if not_executable.overlapping(interval).next().is_some() {
+            continue;
+        }
+        // adjacent executable regions are merged in the set, so the interval is decoded in parts, one per region
+        // (an instruction crossing the region boundary is reported as invalid)
+        for region in sample
+            .memory
+            .iter()
+            .filter(|item| item.protection.contains(Protection::EXECUTE))
+        {
+            let part =
+                interval.intersection(Interval::from_start_and_end(region.addr, region.end()));
+            if part.is_empty() {
+                continue;
+            }
+
+            // decode up to the end of the region to see whether the last instruction fits into the part
+            let mut decoder = iced_x86::Decoder::new(
+                sample.architecture.bitness(),
+                &region.data[(part.start() - region.addr) as usize..],
+                DecoderOptions::NONE,
+            );
+            decoder.set_ip(part.start() as u64);
+
+            while (decoder.ip() as u32) < part.end() {
+                let instr = decoder.decode();
+                if instr.is_invalid() {
+                    issues.push(ValidationIssue::InvalidInstruction {
+                        interval_start: part.start(),
+                        interval_end: part.end(),
+                        address: instr.ip32(),
+                    });
+                    break;
+                }
+
+                instruction_starts.insert(instr.ip32());
+                if instr.op_kinds().any(|k| {
+                    matches!(
+                        k,
+                        OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+                    )
+                }) {
+                    if let Ok(target) = u32::try_from(instr.near_branch_target()) {
+                        jumps.push((instr.ip32(), target));
+                    }
+                }
+
+                if instr.next_ip32() > part.end() {
+                    issues.push(ValidationIssue::DecodingOverrun {
+                        interval_start: part.start(),
+                        interval_end: part.end(),
+                        instruction_end: instr.next_ip32(),
+                    });
+                    break;
+                }
+            }
+        }
+    }
+
+    for (source, target) in jumps {
+        if classes.true_instructions.contains(target) && !instruction_starts.contains(&target) {
+            issues.push(ValidationIssue::JumpIntoInstruction { source, target });
+        }
+    }
+
+    ValidationReport { issues }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::AddressClasses;
+    use memory_image::MemoryImage;
+
+    #[test]
+    fn test_validate_sample() {
+        #[rustfmt::skip]
+        let code = vec![
+            0x55,             // push ebp
+            0xeb, 0x01,       // jmp 0x1004 (into the middle of the next instruction)
+            0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+            0xc3,             // ret
+            0xff, 0xff,       // invalid
+        ];
+
+        let mut memory = MemoryImage::new();
+        memory.add_region(0x1000, Protection::READ_EXECUTE, code, "".to_string());
+        memory.add_region(
+            0x2000,
+            Protection::READ_WRITE,
+            vec![0; 0x10],
+            "".to_string(),
+        );
+
+        let mut classes = AddressClasses::new();
+        // ends in the middle of the mov
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0x1000, 0x1005));
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0x1008, 0x100b));
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0x2000, 0x2004));
+        classes
+            .true_data
+            .push(Interval::from_start_and_end(0x1004, 0x1006));
+
+        let sample = ExecutableSample::new(memory, classes).unwrap();
+
+        assert_eq!(
+            validate_sample(&sample).issues,
+            vec![
+                ValidationIssue::CodeDataOverlap {
+                    start: 0x1004,
+                    end: 0x1005
+                },
+                ValidationIssue::CodeNotExecutable {
+                    start: 0x2000,
+                    end: 0x2004
+                },
+                ValidationIssue::DecodingOverrun {
+                    interval_start: 0x1000,
+                    interval_end: 0x1005,
+                    instruction_end: 0x1008
+                },
+                ValidationIssue::InvalidInstruction {
+                    interval_start: 0x1008,
+                    interval_end: 0x100b,
+                    address: 0x1009
+                },
+                ValidationIssue::JumpIntoInstruction {
+                    source: 0x1001,
+                    target: 0x1004
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interval_across_regions() {
+        let mut memory = MemoryImage::new();
+        #[rustfmt::skip]
+        memory.add_region(0x1000, Protection::READ_EXECUTE, vec![
+            0xeb, 0x03, // jmp 0x1005 (into the middle of the mov in the next region)
+        ], ".init".to_string());
+        #[rustfmt::skip]
+        memory.add_region(0x1002, Protection::READ_EXECUTE, vec![
+            0x90,                         // nop
+            0x90,                         // nop
+            0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+            0xb8, 0x01, 0x00,             // mov eax, ... (cut off by the region end)
+        ], ".text".to_string());
+        memory.add_region(
+            0x100c,
+            Protection::READ_EXECUTE,
+            vec![0x90, 0xc3, 0xff], // nop, ret, invalid
+            ".fini".to_string(),
+        );
+
+        let mut classes = AddressClasses::new();
+        // merged into a single interval spanning all three regions
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0x1000, 0x1002));
+        classes
+            .true_instructions
+            .push(Interval::from_start_and_end(0x1002, 0x100f));
+        assert_eq!(classes.true_instructions.iter().count(), 1);
+
+        let sample = ExecutableSample::new(memory, classes).unwrap();
+
+        assert_eq!(
+            validate_sample(&sample).issues,
+            vec![
+                ValidationIssue::InvalidInstruction {
+                    interval_start: 0x1002,
+                    interval_end: 0x100c,
+                    address: 0x1009
+                },
+                ValidationIssue::InvalidInstruction {
+                    interval_start: 0x100c,
+                    interval_end: 0x100f,
+                    address: 0x100e
+                },
+                ValidationIssue::JumpIntoInstruction {
+                    source: 0x1000,
+                    target: 0x1005
+                },
+            ]
+        );
+    }
+}
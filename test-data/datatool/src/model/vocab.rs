@@ -3,6 +3,7 @@ use anyhow::Result;
 use iced_x86::Code;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Read};
@@ -27,7 +28,7 @@ impl CodeVocabBuilder {
     }
 
     pub fn add_sample(&mut self, sample: &SupersetSample) {
-        for (_, instr, _) in sample.superset.iter() {
+        for instr in sample.instructions.iter() {
             self.add(instr.code);
         }
     }
@@ -76,7 +77,8 @@ pub struct CodeVocab {
 impl CodeVocab {
     const SPECIAL_VALUE_COUNT: usize = 2;
     const INVALID: usize = 0;
-    const UNKNOWN: usize = 1;
+    /// The index of the codes not in the vocab (including [`Code::INVALID`])
+    pub const UNKNOWN: usize = 1;
 
     pub fn new(codes: Vec<Code>) -> Self {
         Self {
@@ -118,6 +120,13 @@ impl CodeVocab {
         }
         Ok(())
     }
+
+    /// Hex SHA-256 of the vocab file written by [`Self::serialize_to`]
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        self.serialize_to(&mut hasher).expect("Hashing can't fail");
+        hex::encode(hasher.finalize())
+    }
 }
 
 impl Index<usize> for CodeVocab {